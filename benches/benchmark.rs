@@ -33,6 +33,7 @@ fn wrapper(bench: &mut Bencher, code: &str, data: &str) {
         gas_limit: 80000,
         gas_price: U256::from(1),
         input: hex::decode(data).unwrap(),
+        ..Default::default()
     };
 
     bench.iter(|| {
@@ -218,6 +219,7 @@ fn bench_new_contract(bench: &mut Bencher) {
                 gas_limit: 8_000_000,
                 gas_price: U256::from(1),
                 input: hex::decode(ERC20_CODE).unwrap(),
+                ..Default::default()
             };
             let r = cita_vm::exec(
                 block_data_provider.clone(),
@@ -264,6 +266,7 @@ fn bench_erc20(bench: &mut Bencher) {
         gas_limit: 8_000_000,
         gas_price: U256::from(1),
         input: hex::decode(ERC20_CODE).unwrap(),
+        ..Default::default()
     };
     let r = cita_vm::exec(
         block_data_provider.clone(),
@@ -294,6 +297,7 @@ fn bench_erc20(bench: &mut Bencher) {
         "a9059cbb0000000000000000000000001000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000a",
                 )
                 .unwrap(),
+                ..Default::default()
             };
             cita_vm::exec(
                 block_data_provider.clone(),
@@ -42,6 +42,7 @@ fn main() {
         gas_limit: 80000,
         gas_price: U256::from(1),
         input: hex::decode("60fe47b1000000000000000000000000000000000000000000000000000000000000002a").unwrap(),
+        ..Default::default()
     };
     let r = cita_vm::exec(
         block_data_provider.clone(),
@@ -61,6 +62,7 @@ fn main() {
         gas_limit: 80000,
         gas_price: U256::from(1),
         input: hex::decode("6d4ce63c").unwrap(),
+        ..Default::default()
     };
     let r = cita_vm::exec(block_data_provider, state_data_provider, context, config, tx).unwrap();
     println!("return={:?}", r);
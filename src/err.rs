@@ -2,6 +2,8 @@ use std::error;
 use std::fmt;
 use std::io;
 
+use ethereum_types::{Address, U256};
+
 use crate::evm;
 use crate::state::Error as StateError;
 
@@ -14,12 +16,22 @@ pub enum Error {
     Str(String),
     NotEnoughBaseGas,
     NotEnoughBalance,
-    InvalidNonce,
+    InvalidNonce { expected: U256, actual: U256 },
+    InvalidBlobTransaction,
+    BlobGasPriceTooLow,
     ContractAlreadyExist,
     ExccedMaxCodeSize,
+    ExccedMaxInitCodeSize,
     ExccedMaxBlockGasLimit,
+    ExccedAccountQuotaLimit,
+    NoTransactionPermission,
+    NoContractPermission,
+    NoCallPermission(Address),
+    NoAmendPermission,
     ExccedMaxCallDepth,
     CreateInStaticCall,
+    CallInDryRunCreate,
+    Reverted(Vec<u8>),
 }
 
 impl error::Error for Error {}
@@ -33,12 +45,24 @@ impl fmt::Display for Error {
             Error::Str(e) => write!(f, "{:?}", e),
             Error::NotEnoughBaseGas => write!(f, "NotEnoughBaseGas"),
             Error::NotEnoughBalance => write!(f, "NotEnoughBalance"),
-            Error::InvalidNonce => write!(f, "InvalidNonce"),
+            Error::InvalidNonce { expected, actual } => {
+                write!(f, "InvalidNonce: expected {}, got {}", expected, actual)
+            }
+            Error::InvalidBlobTransaction => write!(f, "InvalidBlobTransaction"),
+            Error::BlobGasPriceTooLow => write!(f, "BlobGasPriceTooLow"),
             Error::ContractAlreadyExist => write!(f, "ContractAlreadyExist"),
             Error::ExccedMaxCodeSize => write!(f, "ExccedMaxCodeSize"),
+            Error::ExccedMaxInitCodeSize => write!(f, "ExccedMaxInitCodeSize"),
             Error::ExccedMaxBlockGasLimit => write!(f, "ExccedMaxBlockGasLimit"),
+            Error::ExccedAccountQuotaLimit => write!(f, "ExccedAccountQuotaLimit"),
+            Error::NoTransactionPermission => write!(f, "NoTransactionPermission"),
+            Error::NoContractPermission => write!(f, "NoContractPermission"),
+            Error::NoCallPermission(address) => write!(f, "NoCallPermission: {:?}", address),
+            Error::NoAmendPermission => write!(f, "NoAmendPermission"),
             Error::ExccedMaxCallDepth => write!(f, "ExccedMaxCallDepth"),
             Error::CreateInStaticCall => write!(f, "CreateInStaticCall"),
+            Error::CallInDryRunCreate => write!(f, "CallInDryRunCreate"),
+            Error::Reverted(e) => write!(f, "Reverted: 0x{}", hex::encode(e)),
         }
     }
 }
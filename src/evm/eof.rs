@@ -0,0 +1,356 @@
+// EVM Object Format(EOF) container parsing and validation.
+//
+// See:
+//   EIP 3540: EOF - EVM Object Format v1
+//   EIP 3670: EOF - Code Validation
+//   EIP 4200: EOF - Static relative jumps
+//   EIP 4750: EOF - Functions
+//   EIP 5450: EOF - Stack Validation
+use std::ops::Range;
+
+use crate::evm::err;
+use crate::evm::opcodes::OpCode;
+
+pub const MAGIC: [u8; 2] = [0xef, 0x00];
+pub const VERSION: u8 = 0x01;
+
+const KIND_TYPES: u8 = 0x01;
+const KIND_CODE: u8 = 0x02;
+const KIND_DATA: u8 = 0x03;
+const TERMINATOR: u8 = 0x00;
+
+const MAX_CODE_SECTIONS: usize = 1024;
+const MAX_STACK_HEIGHT: u64 = 1023;
+const MAX_SECTION_IO: u8 = 127;
+const STACK_LIMIT: u64 = 1024;
+
+/// Inputs, outputs and max stack height of a code section. See: EIP 4750
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TypeSection {
+    pub inputs: u8,
+    pub outputs: u8,
+    pub max_stack_height: u16,
+}
+
+/// A parsed EOF container. Sections are kept as ranges into the raw code, so
+/// the program counter of the interpreter is still an offset of the raw code.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Container {
+    pub types: Vec<TypeSection>,
+    pub code: Vec<Range<usize>>,
+    pub data: Range<usize>,
+}
+
+/// Returns whether the code is in EOF.
+pub fn is_eof(code: &[u8]) -> bool {
+    code.starts_with(&MAGIC)
+}
+
+fn read_u16(code: &[u8], pos: usize) -> Result<u16, err::Error> {
+    if pos + 2 > code.len() {
+        return Err(err::Error::InvalidEof);
+    }
+    Ok(u16::from_be_bytes([code[pos], code[pos + 1]]))
+}
+
+fn read_i16(code: &[u8], pos: usize) -> Result<i64, err::Error> {
+    read_u16(code, pos).map(|n| i64::from(n as i16))
+}
+
+fn expect_kind(code: &[u8], pos: usize, kind: u8) -> Result<(), err::Error> {
+    match code.get(pos) {
+        Some(k) if *k == kind => Ok(()),
+        _ => Err(err::Error::InvalidEof),
+    }
+}
+
+/// Parse the header and the type section of an EOF container. The code
+/// sections are not validated, see `validate`.
+pub fn parse(code: &[u8]) -> Result<Container, err::Error> {
+    if !is_eof(code) || code.get(2) != Some(&VERSION) {
+        return Err(err::Error::InvalidEof);
+    }
+    let mut pos = 3;
+    expect_kind(code, pos, KIND_TYPES)?;
+    let types_size = read_u16(code, pos + 1)? as usize;
+    pos += 3;
+    expect_kind(code, pos, KIND_CODE)?;
+    let num_code = read_u16(code, pos + 1)? as usize;
+    pos += 3;
+    if num_code == 0 || num_code > MAX_CODE_SECTIONS || types_size != num_code * 4 {
+        return Err(err::Error::InvalidEof);
+    }
+    let mut code_sizes = Vec::with_capacity(num_code);
+    for _ in 0..num_code {
+        let size = read_u16(code, pos)? as usize;
+        if size == 0 {
+            return Err(err::Error::InvalidEof);
+        }
+        code_sizes.push(size);
+        pos += 2;
+    }
+    expect_kind(code, pos, KIND_DATA)?;
+    let data_size = read_u16(code, pos + 1)? as usize;
+    pos += 3;
+    expect_kind(code, pos, TERMINATOR)?;
+    pos += 1;
+
+    // The body must match the header exactly.
+    let body_size = types_size + code_sizes.iter().sum::<usize>() + data_size;
+    if code.len() != pos + body_size {
+        return Err(err::Error::InvalidEof);
+    }
+    let mut types = Vec::with_capacity(num_code);
+    for i in 0..num_code {
+        let t = TypeSection {
+            inputs: code[pos + i * 4],
+            outputs: code[pos + i * 4 + 1],
+            max_stack_height: read_u16(code, pos + i * 4 + 2)?,
+        };
+        if t.inputs > MAX_SECTION_IO
+            || t.outputs > MAX_SECTION_IO
+            || t.max_stack_height < u16::from(t.inputs)
+            || u64::from(t.max_stack_height) > MAX_STACK_HEIGHT
+        {
+            return Err(err::Error::InvalidEof);
+        }
+        types.push(t);
+    }
+    // The first code section is the entry point, it takes and returns nothing.
+    if types[0].inputs != 0 || types[0].outputs != 0 {
+        return Err(err::Error::InvalidEof);
+    }
+    pos += types_size;
+    let mut sections = Vec::with_capacity(num_code);
+    for size in code_sizes {
+        sections.push(pos..pos + size);
+        pos += size;
+    }
+    Ok(Container {
+        types,
+        code: sections,
+        data: pos..pos + data_size,
+    })
+}
+
+/// Parse an EOF container and validate all of its code sections.
+pub fn validate(code: &[u8]) -> Result<Container, err::Error> {
+    let container = parse(code)?;
+    for i in 0..container.code.len() {
+        validate_section(code, &container, i)?;
+    }
+    Ok(container)
+}
+
+// Size of the immediate data following the instruction at pos.
+fn immediate_size(code: &[u8], op: &OpCode, pos: usize) -> Result<usize, err::Error> {
+    Ok(match op {
+        OpCode::RJUMP | OpCode::RJUMPI | OpCode::CALLF => 2,
+        OpCode::RJUMPV => match code.get(pos + 1) {
+            Some(max_index) => 1 + 2 * (*max_index as usize + 1),
+            None => return Err(err::Error::InvalidEof),
+        },
+        _ if *op >= OpCode::PUSH1 && *op <= OpCode::PUSH32 => (op.clone() as u8 - OpCode::PUSH1 as u8 + 1) as usize,
+        _ => 0,
+    })
+}
+
+fn is_terminating(op: &OpCode) -> bool {
+    matches!(
+        op,
+        OpCode::STOP | OpCode::RETURN | OpCode::REVERT | OpCode::INVALID | OpCode::RETF | OpCode::RJUMP
+    )
+}
+
+// Returns the relative jump targets of the instruction at pos, as offsets of
+// the raw code.
+fn jump_targets(code: &[u8], op: &OpCode, pos: usize) -> Result<Vec<i64>, err::Error> {
+    Ok(match op {
+        OpCode::RJUMP | OpCode::RJUMPI => vec![pos as i64 + 3 + read_i16(code, pos + 1)?],
+        OpCode::RJUMPV => {
+            let count = code[pos + 1] as usize + 1;
+            let end = (pos + 2 + 2 * count) as i64;
+            let mut targets = Vec::with_capacity(count);
+            for i in 0..count {
+                targets.push(end + read_i16(code, pos + 2 + 2 * i)?);
+            }
+            targets
+        }
+        _ => vec![],
+    })
+}
+
+fn validate_section(code: &[u8], container: &Container, index: usize) -> Result<(), err::Error> {
+    let range = container.code[index].clone();
+    let section_type = &container.types[index];
+
+    // Code validation: only defined opcodes and no truncated immediate
+    // data. See: EIP 3670
+    let mut is_instruction = vec![false; range.len()];
+    let mut pos = range.start;
+    while pos < range.end {
+        let op = OpCode::from_u8(code[pos]).ok_or(err::Error::InvalidEof)?;
+        // Dynamic jumps, PC, CALLCODE and SELFDESTRUCT are not allowed in EOF.
+        if matches!(
+            op,
+            OpCode::JUMP | OpCode::JUMPI | OpCode::PC | OpCode::CALLCODE | OpCode::SELFDESTRUCT
+        ) {
+            return Err(err::Error::InvalidEof);
+        }
+        is_instruction[pos - range.start] = true;
+        pos += 1 + immediate_size(code, &op, pos)?;
+    }
+    if pos != range.end {
+        return Err(err::Error::InvalidEof);
+    }
+
+    // Stack validation: every instruction is reachable, every path reaches
+    // it with the same stack height and no path underflows or falls off the
+    // end of the section. See: EIP 5450
+    let mut heights: Vec<Option<u64>> = vec![None; range.len()];
+    let mut max_height = u64::from(section_type.inputs);
+    heights[0] = Some(max_height);
+    let mut worklist = vec![range.start];
+    while let Some(pos) = worklist.pop() {
+        let op = OpCode::from_u8(code[pos]).ok_or(err::Error::InvalidEof)?;
+        let height = heights[pos - range.start].ok_or(err::Error::InvalidEof)?;
+        let (require, returns) = match op {
+            OpCode::CALLF => {
+                let target = read_u16(code, pos + 1)? as usize;
+                let target_type = container.types.get(target).ok_or(err::Error::InvalidEof)?;
+                if height + u64::from(target_type.max_stack_height) - u64::from(target_type.inputs) > STACK_LIMIT {
+                    return Err(err::Error::InvalidEof);
+                }
+                (u64::from(target_type.inputs), u64::from(target_type.outputs))
+            }
+            _ => (op.stack_require(), op.stack_returns()),
+        };
+        if height < require {
+            return Err(err::Error::InvalidEof);
+        }
+        let next_height = height - require + returns;
+        max_height = std::cmp::max(max_height, next_height);
+        if op == OpCode::RETF && height != u64::from(section_type.outputs) {
+            return Err(err::Error::InvalidEof);
+        }
+
+        let next = pos + 1 + immediate_size(code, &op, pos)?;
+        let mut successors = vec![];
+        if !is_terminating(&op) {
+            successors.push(next as i64);
+        }
+        successors.extend(jump_targets(code, &op, pos)?);
+        for s in successors {
+            if s < range.start as i64 || s >= range.end as i64 || !is_instruction[s as usize - range.start] {
+                return Err(err::Error::InvalidEof);
+            }
+            match heights[s as usize - range.start] {
+                Some(h) if h != next_height => return Err(err::Error::InvalidEof),
+                Some(_) => {}
+                None => {
+                    heights[s as usize - range.start] = Some(next_height);
+                    worklist.push(s as usize);
+                }
+            }
+        }
+    }
+    for (i, is) in is_instruction.iter().enumerate() {
+        if *is && heights[i].is_none() {
+            return Err(err::Error::InvalidEof);
+        }
+    }
+    if max_height != u64::from(section_type.max_stack_height) {
+        return Err(err::Error::InvalidEof);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Build a container with the given (inputs, outputs, max_stack_height, code) sections.
+    fn build(sections: &[(u8, u8, u16, &str)], data: &str) -> Vec<u8> {
+        let data = hex::decode(data).unwrap();
+        let mut r = vec![0xef, 0x00, VERSION, KIND_TYPES];
+        r.extend_from_slice(&((sections.len() * 4) as u16).to_be_bytes());
+        r.push(KIND_CODE);
+        r.extend_from_slice(&(sections.len() as u16).to_be_bytes());
+        for s in sections {
+            r.extend_from_slice(&((s.3.len() / 2) as u16).to_be_bytes());
+        }
+        r.push(KIND_DATA);
+        r.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        r.push(TERMINATOR);
+        for s in sections {
+            r.push(s.0);
+            r.push(s.1);
+            r.extend_from_slice(&s.2.to_be_bytes());
+        }
+        for s in sections {
+            r.extend(hex::decode(s.3).unwrap());
+        }
+        r.extend(data);
+        r
+    }
+
+    #[test]
+    fn test_parse() {
+        let code = build(&[(0, 0, 0, "00")], "aabb");
+        let c = parse(&code).unwrap();
+        assert_eq!(c.code, vec![19..20]);
+        assert_eq!(c.data, 20..22);
+        assert!(validate(&code).is_ok());
+
+        // Truncated body, trailing bytes and wrong version.
+        assert!(parse(&code[..code.len() - 1]).is_err());
+        assert!(parse(&[code.clone(), vec![0x00]].concat()).is_err());
+        let mut bad = code.clone();
+        bad[2] = 0x02;
+        assert!(parse(&bad).is_err());
+        // The first section must take and return nothing.
+        assert!(parse(&build(&[(1, 0, 1, "50e4")], "")).is_err());
+    }
+
+    #[test]
+    fn test_validate_code() {
+        // Undefined opcode, truncated PUSH and deprecated JUMP.
+        assert!(validate(&build(&[(0, 0, 0, "0c00")], "")).is_err());
+        assert!(validate(&build(&[(0, 0, 1, "6100")], "")).is_err());
+        assert!(validate(&build(&[(0, 0, 1, "600056")], "")).is_err());
+        // Falls off the end of the section.
+        assert!(validate(&build(&[(0, 0, 1, "6001")], "")).is_err());
+    }
+
+    #[test]
+    fn test_validate_jumps() {
+        // PUSH1 1, RJUMPI +1, STOP, STOP
+        assert!(validate(&build(&[(0, 0, 1, "6001e100010000")], "")).is_ok());
+        // Jumps into the immediate data of PUSH1.
+        assert!(validate(&build(&[(0, 0, 1, "6001e1fffc00")], "")).is_err());
+        // Jumps out of the section.
+        assert!(validate(&build(&[(0, 0, 1, "6001e1000500")], "")).is_err());
+        // PUSH1 0, RJUMPV [0, 1], STOP, STOP
+        assert!(validate(&build(&[(0, 0, 1, "6000e201000000010000")], "")).is_ok());
+        // Unreachable code after RJUMP.
+        assert!(validate(&build(&[(0, 0, 0, "e000010000")], "")).is_err());
+    }
+
+    #[test]
+    fn test_validate_functions() {
+        // Section 0: PUSH1 1, CALLF 1, POP, STOP; section 1: DUP1, ADD, RETF
+        let sections = [(0, 0, 1, "6001e300015000"), (1, 1, 2, "8001e4")];
+        assert!(validate(&build(&sections, "")).is_ok());
+        // Wrong max stack height.
+        let sections = [(0, 0, 2, "6001e300015000"), (1, 1, 2, "8001e4")];
+        assert!(validate(&build(&sections, "")).is_err());
+        // Calls a section that does not exist.
+        assert!(validate(&build(&[(0, 0, 0, "e3000100")], "")).is_err());
+        // Underflow: section 1 takes one input but none is given.
+        let sections = [(0, 0, 1, "e300015000"), (1, 1, 2, "8001e4")];
+        assert!(validate(&build(&sections, "")).is_err());
+        // RETF with the wrong stack height.
+        let sections = [(0, 0, 1, "6001e300015000"), (1, 1, 2, "80e4")];
+        assert!(validate(&build(&sections, "")).is_err());
+    }
+}
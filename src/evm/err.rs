@@ -10,9 +10,14 @@ pub enum Error {
     OutOfData,
     MutableCallInStaticContext,
     InvalidOpcode,
+    InvalidInstruction,
     CallError,
+    PermissionDenied { gas_left: u64 }, // A nested frame denied by the authorizer, with the gas given back
     ExccedMaxCodeSize,
+    ExccedMaxInitCodeSize,
     InvalidJumpDestination,
+    InvalidCode,
+    InvalidEof,
     Internal(String),
     StackUnderflow,
 }
@@ -28,9 +33,14 @@ impl fmt::Display for Error {
             Error::OutOfData => write!(f, "OutOfData"),
             Error::MutableCallInStaticContext => write!(f, "MutableCallInStaticContext"),
             Error::InvalidOpcode => write!(f, "InvalidOpcode"),
+            Error::InvalidInstruction => write!(f, "InvalidInstruction"),
             Error::CallError => write!(f, "CallError"),
+            Error::PermissionDenied { gas_left } => write!(f, "PermissionDenied gas_left={}", gas_left),
             Error::ExccedMaxCodeSize => write!(f, "ExccedMaxCodeSize"),
+            Error::ExccedMaxInitCodeSize => write!(f, "ExccedMaxInitCodeSize"),
             Error::InvalidJumpDestination => write!(f, "InvalidJumpDestination"),
+            Error::InvalidCode => write!(f, "InvalidCode"),
+            Error::InvalidEof => write!(f, "InvalidEof"),
             Error::Internal(err) => write!(f, "Internal error {}", err),
             Error::StackUnderflow => write!(f, "StackUnderflow"),
         }
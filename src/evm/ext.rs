@@ -7,9 +7,9 @@ use crate::evm::opcodes;
 pub trait DataProvider {
     fn get_balance(&self, address: &Address) -> U256;
 
-    fn add_refund(&mut self, address: &Address, n: u64);
-    fn sub_refund(&mut self, address: &Address, n: u64);
-    fn get_refund(&self, address: &Address) -> u64;
+    fn add_refund(&mut self, n: u64);
+    fn sub_refund(&mut self, n: u64);
+    fn get_refund(&self) -> u64;
 
     fn get_code_size(&self, address: &Address) -> u64;
     fn get_code(&self, address: &Address) -> Vec<u8>;
@@ -21,6 +21,9 @@ pub trait DataProvider {
     fn set_storage(&mut self, address: &Address, key: H256, value: H256);
     fn get_storage_origin(&self, address: &Address, key: &H256) -> H256;
     fn set_storage_origin(&mut self, address: &Address, key: H256, value: H256);
+    // Transient storage lives for a transaction and is shared by all frames. See: EIP 1153
+    fn get_transient_storage(&self, address: &Address, key: &H256) -> H256;
+    fn set_transient_storage(&mut self, address: &Address, key: H256, value: H256);
 
     fn selfdestruct(&mut self, address: &Address, refund_address: &Address) -> bool;
     fn sha3(&self, input: &[u8]) -> H256;
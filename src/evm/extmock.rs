@@ -20,7 +20,8 @@ pub struct Account {
 pub struct DataProviderMock {
     pub db: BTreeMap<Address, Account>,
     pub db_origin: BTreeMap<Address, Account>,
-    pub refund: BTreeMap<Address, u64>,
+    pub db_transient: BTreeMap<Address, BTreeMap<H256, H256>>,
+    pub refund: u64,
 }
 
 impl ext::DataProvider for DataProviderMock {
@@ -28,16 +29,16 @@ impl ext::DataProvider for DataProviderMock {
         self.db.get(address).map_or(U256::zero(), |v| v.balance)
     }
 
-    fn add_refund(&mut self, address: &Address, n: u64) {
-        self.refund.entry(*address).and_modify(|v| *v += n).or_insert(n);
+    fn add_refund(&mut self, n: u64) {
+        self.refund += n;
     }
 
-    fn sub_refund(&mut self, address: &Address, n: u64) {
-        self.refund.entry(*address).and_modify(|v| *v -= n).or_insert(n);
+    fn sub_refund(&mut self, n: u64) {
+        self.refund = self.refund.saturating_sub(n);
     }
 
-    fn get_refund(&self, address: &Address) -> u64 {
-        self.refund.get(address).map_or(0, |v| *v)
+    fn get_refund(&self) -> u64 {
+        self.refund
     }
 
     fn get_code_size(&self, address: &Address) -> u64 {
@@ -78,6 +79,16 @@ impl ext::DataProvider for DataProviderMock {
         self.db_origin.entry(*address).or_default().storage.insert(key, value);
     }
 
+    fn get_transient_storage(&self, address: &Address, key: &H256) -> H256 {
+        self.db_transient
+            .get(address)
+            .map_or(H256::zero(), |v| v.get(key).map_or(H256::zero(), |&v| v))
+    }
+
+    fn set_transient_storage(&mut self, address: &Address, key: H256, value: H256) {
+        self.db_transient.entry(*address).or_default().insert(key, value);
+    }
+
     fn selfdestruct(&mut self, address: &Address, _: &Address) -> bool {
         self.db.remove(address);
         true
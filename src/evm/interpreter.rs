@@ -1,10 +1,10 @@
 use std::cmp;
 
 use ethereum_types::{Address, BigEndianHash, H256, U256, U512};
-use hashbrown::HashMap;
 use log::debug;
 
 use crate::evm::common;
+use crate::evm::eof;
 use crate::evm::err;
 use crate::evm::ext;
 use crate::evm::memory;
@@ -18,6 +18,7 @@ pub struct Context {
     pub number: U256,
     pub timestamp: u64,
     pub difficulty: U256,
+    pub excess_blob_gas: u64, // See: EIP 4844
 }
 
 // Log is the data struct for LOG0...LOG4.
@@ -37,11 +38,16 @@ pub enum InterpreterResult {
 
 #[derive(Clone, Debug)]
 pub struct InterpreterConf {
-    pub no_empty: bool,
+    pub no_empty: bool, // Empty accounts are treated as non-existent and removed once touched, eip161
     pub eip1283: bool,
+    pub eip3541: bool, // Reject new code starting with 0xEF, enabled since London and by default
+    pub eof: bool,     // Validate and execute EOF code, eip3540, eip3670, eip4200, eip4750, eip5450
+    pub eip6780: bool, // SELFDESTRUCT only deletes contracts created in the same transaction
     pub stack_limit: u64,
-    pub max_create_code_size: u64, // See: https://github.com/ethereum/EIPs/issues/659
+    pub max_create_code_size: u64, // Max size of the deployed code, 24576 by default and in EIP 170
+    pub max_initcode_size: u64,    // Max size of the initcode, 49152 by default and in EIP 3860
     pub max_call_depth: u64,
+    pub max_refund_quotient: u64, // Refund is capped at gas used divided by it, 5 in EIP 3529, 0 for no refund
 
     pub gas_tier_step: [u64; 8],
     pub gas_exp: u64,                       // Partial payment for an EXP operation.
@@ -66,6 +72,8 @@ pub struct InterpreterConf {
     pub gas_log_data: u64,     // Paid for each byte in a LOG operation’s data.
     pub gas_log_topic: u64,    // Paid for each topic of a LOG operation.
     pub gas_create: u64,       // Paid for a CREATE operation
+    pub gas_initcode_word: u64, // Paid for every word of the initcode of a CREATE operation, eip3860
+    pub gas_rjumpi: u64,       // Paid for a RJUMPI or RJUMPV operation, eip4200
     pub gas_jumpdest: u64,     // Paid for a JUMPDEST operation
     pub gas_copy: u64,         // Partial payment for *COPY operations, multiplied by words copied, rounded up.
     pub gas_call: u64,         // Paid for a CALL operation.
@@ -91,9 +99,14 @@ impl Default for InterpreterConf {
         InterpreterConf {
             no_empty: false,
             eip1283: false,
+            eip3541: true,
+            eof: false,
+            eip6780: false,
             stack_limit: 1024,
-            max_create_code_size: std::u64::MAX,
+            max_create_code_size: 24576,
+            max_initcode_size: 49152,
             max_call_depth: 1024,
+            max_refund_quotient: 2,
 
             gas_tier_step: [0, 2, 3, 5, 8, 10, 20, 0],
             gas_exp: 10,
@@ -118,6 +131,8 @@ impl Default for InterpreterConf {
             gas_log_data: 8,
             gas_log_topic: 375,
             gas_create: 32000,
+            gas_initcode_word: 0,
+            gas_rjumpi: 4,
             gas_jumpdest: 1,
             gas_copy: 3,
             gas_call: 40, //700,
@@ -136,6 +151,49 @@ impl Default for InterpreterConf {
     }
 }
 
+impl InterpreterConf {
+    /// Returns the default configs with the rules of the given spec enabled.
+    /// Gas costs stay CITA's, except the refunds and initcode metering which
+    /// are part of the rules.
+    pub fn from_spec(spec: Spec) -> Self {
+        let mut cfg = InterpreterConf {
+            no_empty: true,
+            eip3541: false,
+            max_initcode_size: u64::MAX,
+            ..Default::default()
+        };
+        if spec >= Spec::London {
+            cfg.eip3541 = true;
+            cfg.max_refund_quotient = 5;
+            cfg.gas_self_destruct_refund = 0;
+            cfg.gas_sstore_clear_refund = 4800;
+            cfg.gas_sstore_refund = 4800;
+            // The refunds of resetting a dirty slot to its original value are
+            // the costs of SSTORE minus the warm SLOAD of EIP 2929 in EIP 3529.
+            // Without access costs, they stay at the costs minus SLOAD of EIP 2200.
+        }
+        if spec >= Spec::Shanghai {
+            cfg.max_initcode_size = 49152;
+            cfg.gas_initcode_word = 2;
+        }
+        if spec >= Spec::Cancun {
+            cfg.eip6780 = true;
+        }
+        cfg
+    }
+}
+
+/// Hard forks which change the rules of the interpreter, in order. Only forks
+/// with rules supported by the interpreter are listed, and a spec does not
+/// select the gas table of Ethereum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Spec {
+    Constantinople,
+    London,   // EIP 3529, EIP 3541
+    Shanghai, // EIP 3860
+    Cancun,   // EIP 6780
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct Contract {
     pub code_address: Address,
@@ -156,6 +214,7 @@ pub struct InterpreterParams {
     pub gas_price: U256,
     pub base_fee: U256,
     pub blob_base_fee: U256,
+    pub blob_versioned_hashes: Vec<H256>,
 
     pub read_only: bool,
     pub contract: Contract,
@@ -178,7 +237,8 @@ pub struct Interpreter {
     return_data: Vec<u8>,
     mem_gas: u64,
     gas_tmp: u64,
-    transient_storage: HashMap<Address, HashMap<H256, H256>>,
+    eof: Option<eof::Container>, // Some if the code is in EOF
+    return_stack: Vec<u64>,      // Return addresses of CALLF
 }
 
 impl Interpreter {
@@ -201,15 +261,34 @@ impl Interpreter {
             return_data: Vec::new(),
             mem_gas: 0,
             gas_tmp: 0,
-            transient_storage: HashMap::new(),
+            eof: None,
+            return_stack: Vec::new(),
         }
     }
 
     #[allow(clippy::cognitive_complexity)]
     pub fn run(&mut self) -> Result<InterpreterResult, err::Error> {
         let mut pc = 0;
+        if self.cfg.eof && eof::is_eof(&self.params.contract.code_data) {
+            let container = eof::validate(&self.params.contract.code_data)?;
+            pc = container.code[0].start as u64;
+            self.eof = Some(container);
+        }
         while let Some(op) = self.get_op(pc)? {
             pc += 1;
+            // Ensure EOF only instructions are not used in legacy code
+            if self.eof.is_none()
+                && matches!(
+                    op,
+                    opcodes::OpCode::RJUMP
+                        | opcodes::OpCode::RJUMPI
+                        | opcodes::OpCode::RJUMPV
+                        | opcodes::OpCode::CALLF
+                        | opcodes::OpCode::RETF
+                )
+            {
+                return Err(err::Error::InvalidOpcode);
+            }
             // Trace the execution informations
             self.trace(&op, pc);
             // Ensure stack
@@ -328,28 +407,23 @@ impl Interpreter {
                                     self.cfg.gas_sstore_init
                                 } else {
                                     if new_value.is_zero() {
-                                        self.data_provider
-                                            .add_refund(&self.params.origin, self.cfg.gas_sstore_clear_refund);
+                                        self.data_provider.add_refund(self.cfg.gas_sstore_clear_refund);
                                     }
                                     self.cfg.gas_sstore_clean
                                 }
                             } else {
                                 if !original_value.is_zero() {
                                     if current_value.is_zero() {
-                                        self.data_provider
-                                            .sub_refund(&self.params.origin, self.cfg.gas_sstore_clear_refund);
+                                        self.data_provider.sub_refund(self.cfg.gas_sstore_clear_refund);
                                     } else if new_value.is_zero() {
-                                        self.data_provider
-                                            .add_refund(&self.params.origin, self.cfg.gas_sstore_clear_refund);
+                                        self.data_provider.add_refund(self.cfg.gas_sstore_clear_refund);
                                     }
                                 }
                                 if original_value == new_value {
                                     if original_value.is_zero() {
-                                        self.data_provider
-                                            .add_refund(&self.params.origin, self.cfg.gas_sstore_reset_clear_refund);
+                                        self.data_provider.add_refund(self.cfg.gas_sstore_reset_clear_refund);
                                     } else {
-                                        self.data_provider
-                                            .add_refund(&self.params.origin, self.cfg.gas_sstore_reset_refund);
+                                        self.data_provider.add_refund(self.cfg.gas_sstore_reset_refund);
                                     }
                                 }
                                 self.cfg.gas_sstore_dirty
@@ -357,8 +431,7 @@ impl Interpreter {
                         } else if current_value.is_zero() && !new_value.is_zero() {
                             self.cfg.gas_sstore_set
                         } else if !current_value.is_zero() && new_value.is_zero() {
-                            self.data_provider
-                                .add_refund(&self.params.origin, self.cfg.gas_sstore_refund);
+                            self.data_provider.add_refund(self.cfg.gas_sstore_refund);
                             self.cfg.gas_sstore_clear
                         } else {
                             self.cfg.gas_sstore_reset
@@ -369,6 +442,9 @@ impl Interpreter {
                 opcodes::OpCode::JUMPDEST => {
                     self.use_gas(self.cfg.gas_jumpdest)?;
                 }
+                opcodes::OpCode::RJUMPI | opcodes::OpCode::RJUMPV => {
+                    self.use_gas(self.cfg.gas_rjumpi)?;
+                }
                 opcodes::OpCode::TLOAD | opcodes::OpCode::TSTORE => {
                     self.use_gas(self.cfg.gas_transit_storage)?;
                 }
@@ -396,11 +472,12 @@ impl Interpreter {
                 opcodes::OpCode::CREATE => {
                     let mem_offset = self.stack.back(1);
                     let mem_len = self.stack.back(2);
-                    if mem_len > U256::from(self.cfg.max_create_code_size) {
-                        return Err(err::Error::ExccedMaxCodeSize);
+                    if mem_len > U256::from(self.cfg.max_initcode_size) {
+                        return Err(err::Error::ExccedMaxInitCodeSize);
                     }
                     self.mem_gas_work(mem_offset, mem_len)?;
                     self.use_gas(self.cfg.gas_create)?;
+                    self.use_gas(common::to_word_size(mem_len.low_u64()) * self.cfg.gas_initcode_word)?;
                     self.gas_tmp = self.gas - self.gas / 64;
                     self.use_gas(self.gas_tmp)?;
                 }
@@ -450,12 +527,13 @@ impl Interpreter {
                 opcodes::OpCode::CREATE2 => {
                     let mem_offset = self.stack.back(1);
                     let mem_len = self.stack.back(2);
-                    if mem_len > U256::from(self.cfg.max_create_code_size) {
-                        return Err(err::Error::ExccedMaxCodeSize);
+                    if mem_len > U256::from(self.cfg.max_initcode_size) {
+                        return Err(err::Error::ExccedMaxInitCodeSize);
                     }
                     self.mem_gas_work(mem_offset, mem_len)?;
                     self.use_gas(self.cfg.gas_create)?;
                     self.use_gas(common::to_word_size(mem_len.low_u64()) * self.cfg.gas_sha3_word)?;
+                    self.use_gas(common::to_word_size(mem_len.low_u64()) * self.cfg.gas_initcode_word)?;
                     self.gas_tmp = self.gas - self.gas / 64;
                     self.use_gas(self.gas_tmp)?;
                 }
@@ -472,8 +550,7 @@ impl Interpreter {
                     {
                         self.use_gas(self.cfg.gas_self_destruct_new_account)?;
                     }
-                    self.data_provider
-                        .add_refund(&self.params.origin, self.cfg.gas_self_destruct_refund);
+                    self.data_provider.add_refund(self.cfg.gas_self_destruct_refund);
                 }
                 _ => {}
             }
@@ -814,6 +891,15 @@ impl Interpreter {
                 opcodes::OpCode::BASEFEE => {
                     self.stack.push(self.params.base_fee);
                 }
+                opcodes::OpCode::BLOBHASH => {
+                    let index = self.stack.pop();
+                    let hash = if index < U256::from(self.params.blob_versioned_hashes.len()) {
+                        self.params.blob_versioned_hashes[index.low_u64() as usize]
+                    } else {
+                        H256::zero()
+                    };
+                    self.stack.push(U256::from_big_endian(hash.as_bytes()));
+                }
                 opcodes::OpCode::BLOBBASEFEE => {
                     self.stack.push(self.params.blob_base_fee);
                 }
@@ -870,15 +956,60 @@ impl Interpreter {
                     self.stack.push(U256::from(self.gas));
                 }
                 opcodes::OpCode::JUMPDEST => {}
+                opcodes::OpCode::RJUMP => {
+                    let offset = self.get_u16(pc) as i16;
+                    pc = (pc as i64 + 2 + i64::from(offset)) as u64;
+                }
+                opcodes::OpCode::RJUMPI => {
+                    let condition = self.stack.pop();
+                    let offset = self.get_u16(pc) as i16;
+                    pc += 2;
+                    if !condition.is_zero() {
+                        pc = (pc as i64 + i64::from(offset)) as u64;
+                    }
+                }
+                opcodes::OpCode::RJUMPV => {
+                    let case = self.stack.pop();
+                    let count = u64::from(self.get_byte(pc).unwrap_or(0)) + 1;
+                    let end = pc + 1 + 2 * count;
+                    if case < U256::from(count) {
+                        let offset = self.get_u16(pc + 1 + 2 * case.low_u64()) as i16;
+                        pc = (end as i64 + i64::from(offset)) as u64;
+                    } else {
+                        pc = end;
+                    }
+                }
+                opcodes::OpCode::CALLF => {
+                    let index = self.get_u16(pc) as usize;
+                    // Legacy code never reaches here, see above.
+                    let container = self.eof.as_ref().unwrap();
+                    let t = &container.types[index];
+                    if self.stack.len() as u64 + u64::from(t.max_stack_height) - u64::from(t.inputs)
+                        > self.cfg.stack_limit
+                        || self.return_stack.len() as u64 >= self.cfg.stack_limit
+                    {
+                        return Err(err::Error::OutOfStack);
+                    }
+                    self.return_stack.push(pc + 2);
+                    pc = container.code[index].start as u64;
+                }
+                opcodes::OpCode::RETF => match self.return_stack.pop() {
+                    Some(n) => pc = n,
+                    // Returns from the first code section, just like STOP.
+                    None => break,
+                },
                 opcodes::OpCode::TLOAD => {
                     let location = H256::from_uint(&self.stack.pop());
-                    let value = self.get_transit_storage(&self.params.address, &location);
+                    let value = self
+                        .data_provider
+                        .get_transient_storage(&self.params.address, &location);
                     self.stack.push(U256::from_big_endian(&value.0))
                 }
                 opcodes::OpCode::TSTORE => {
                     let location = H256::from_uint(&self.stack.pop());
                     let value = H256::from_uint(&self.stack.pop());
-                    self.set_transit_storage(self.params.address, location, value)
+                    self.data_provider
+                        .set_transient_storage(&self.params.address, location, value)
                 }
                 opcodes::OpCode::MCOPY => {
                     let mem_offset = self.stack.pop().low_u64() as usize;
@@ -1015,6 +1146,9 @@ impl Interpreter {
                     params.sender = self.params.address;
                     params.gas_limit = self.gas_tmp;
                     params.gas_price = self.params.gas_price;
+                    params.base_fee = self.params.base_fee;
+                    params.blob_base_fee = self.params.blob_base_fee;
+                    params.blob_versioned_hashes = self.params.blob_versioned_hashes.clone();
                     params.input = Vec::from(data);
                     params.value = value;
                     params.extra = salt;
@@ -1034,7 +1168,14 @@ impl Interpreter {
                             }
                             _ => {}
                         },
+                        Err(err::Error::PermissionDenied { gas_left }) => {
+                            // A denied frame is only charged the gas of the denial.
+                            self.stack.push(U256::zero());
+                            self.gas += gas_left;
+                        }
                         Err(_) => {
+                            // An exceptional halt consumes all gas forwarded to the frame,
+                            // so gas_tmp is not given back.
                             self.stack.push(U256::zero());
                         }
                     }
@@ -1086,6 +1227,9 @@ impl Interpreter {
                     params.origin = self.params.origin;
                     params.gas_limit = gas;
                     params.gas_price = self.params.gas_price;
+                    params.base_fee = self.params.base_fee;
+                    params.blob_base_fee = self.params.blob_base_fee;
+                    params.blob_versioned_hashes = self.params.blob_versioned_hashes.clone();
                     params.contract.code_address = address;
                     params.contract.code_data = self.data_provider.get_code(&params.contract.code_address).to_vec();
                     params.input = Vec::from(data);
@@ -1161,7 +1305,14 @@ impl Interpreter {
                             }
                             _ => {}
                         },
+                        Err(err::Error::PermissionDenied { gas_left }) => {
+                            // A denied frame is only charged the gas of the denial.
+                            self.stack.push(U256::zero());
+                            self.gas += gas_left;
+                        }
                         Err(_) => {
+                            // An exceptional halt consumes all gas forwarded to the frame,
+                            // so gas_tmp is not given back.
                             self.stack.push(U256::zero());
                         }
                     }
@@ -1180,6 +1331,9 @@ impl Interpreter {
                     let return_data = Vec::from(r);
                     return Ok(InterpreterResult::Revert(return_data, self.gas));
                 }
+                opcodes::OpCode::INVALID => {
+                    return Err(err::Error::InvalidInstruction);
+                }
                 opcodes::OpCode::SELFDESTRUCT => {
                     let address = self.stack.pop();
                     let b = self
@@ -1192,8 +1346,7 @@ impl Interpreter {
                         // Obviously, we should not `add_refund` for each `SELFDESTRUCT`.
                         // But it is difficult to know whether the address has been destructed,
                         // so an eclectic approach is sub the refund after call.
-                        self.data_provider
-                            .sub_refund(&self.params.origin, self.cfg.gas_self_destruct_refund);
+                        self.data_provider.sub_refund(self.cfg.gas_self_destruct_refund);
                     }
                     break;
                 }
@@ -1250,6 +1403,10 @@ impl Interpreter {
         None
     }
 
+    fn get_u16(&self, n: u64) -> u16 {
+        u16::from_be_bytes([self.get_byte(n).unwrap_or(0), self.get_byte(n + 1).unwrap_or(0)])
+    }
+
     fn get_op(&self, n: u64) -> Result<Option<opcodes::OpCode>, err::Error> {
         match self.get_byte(n) {
             Some(a) => match opcodes::OpCode::from_u8(a) {
@@ -1297,25 +1454,6 @@ impl Interpreter {
         }
         debug!("[MEM] len={}", self.mem.len());
     }
-
-    fn get_transit_storage(&self, addr: &Address, location: &H256) -> H256 {
-        if let Some(state) = self.transient_storage.get(addr) {
-            if let Some(v) = state.get(location) {
-                return *v;
-            }
-        }
-        H256::zero()
-    }
-
-    fn set_transit_storage(&mut self, addr: Address, location: H256, value: H256) {
-        if let Some(state) = self.transient_storage.get_mut(&addr) {
-            state.insert(location, value);
-        } else {
-            let mut state = HashMap::new();
-            state.insert(location, value);
-            self.transient_storage.insert(addr, state);
-        }
-    }
 }
 
 #[cfg(test)]
@@ -1842,7 +1980,7 @@ mod tests {
             it.params.contract.code_data = hex::decode(code).unwrap();
             it.run().unwrap();
             assert_eq!(it.gas, it.context.gas_limit - use_gas);
-            assert_eq!(it.data_provider.get_refund(&Address::zero()), refund);
+            assert_eq!(it.data_provider.get_refund(), refund);
         }
     }
 
@@ -1855,6 +1993,77 @@ mod tests {
         assert_eq!(r.err(), Some(err::Error::InvalidOpcode))
     }
 
+    #[test]
+    fn test_op_invalid_instruction() {
+        let mut it = default_interpreter();
+        it.params.contract.code_data = hex::decode("fe").unwrap();
+        assert_eq!(it.run().err(), Some(err::Error::InvalidInstruction));
+        assert_eq!(opcodes::OpCode::from_u8(0xfe), Some(opcodes::OpCode::INVALID));
+    }
+
+    #[test]
+    fn test_op_blob_hash() {
+        let mut it = default_interpreter();
+        let hash = H256::from_low_u64_be(0x0100);
+        it.params.blob_versioned_hashes = vec![H256::zero(), hash];
+        it.params.contract.code_data = vec![opcodes::OpCode::BLOBHASH as u8];
+        it.stack.push(U256::from(1));
+        it.run().unwrap();
+        assert_eq!(it.stack.pop(), U256::from(0x0100));
+
+        // Out of range index returns zero.
+        it.stack.push(U256::from(2));
+        it.run().unwrap();
+        assert_eq!(it.stack.pop(), U256::zero());
+    }
+
+    #[test]
+    fn test_eof() {
+        // Section 0 calls section 1 with 2, which returns 2 + 2, then returns
+        // the result as a word.
+        let code = "ef0001010008020002000d00030300000000000002010100026002e3000160005260206000f38001e4";
+        let mut it = default_interpreter();
+        it.cfg.eof = true;
+        it.params.contract.code_data = hex::decode(code).unwrap();
+        match it.run().unwrap() {
+            InterpreterResult::Normal(output, _, _) => assert_eq!(U256::from_big_endian(&output), U256::from(4)),
+            _ => panic!("normal result expected"),
+        }
+
+        // RJUMPI skips the STOP and returns 2 as a word.
+        let code = "ef0001010004020001001203000000000000026001e10003600100600260005260206000f3";
+        let mut it = default_interpreter();
+        it.cfg.eof = true;
+        it.params.contract.code_data = hex::decode(code).unwrap();
+        match it.run().unwrap() {
+            InterpreterResult::Normal(output, _, _) => assert_eq!(U256::from_big_endian(&output), U256::from(2)),
+            _ => panic!("normal result expected"),
+        }
+
+        // Without the spec flag, EOF code is executed as legacy code.
+        let mut it = default_interpreter();
+        it.params.contract.code_data = hex::decode(code).unwrap();
+        assert_eq!(it.run().err(), Some(err::Error::InvalidOpcode));
+
+        // EOF instructions are invalid in legacy code.
+        let mut it = default_interpreter();
+        it.cfg.eof = true;
+        it.params.contract.code_data = hex::decode("e0000000").unwrap();
+        assert_eq!(it.run().err(), Some(err::Error::InvalidOpcode));
+    }
+
+    #[test]
+    fn test_op_create_max_initcode_size() {
+        for op in [opcodes::OpCode::CREATE, opcodes::OpCode::CREATE2] {
+            let mut it = default_interpreter();
+            it.cfg.max_initcode_size = 32;
+            it.params.contract.code_data = vec![op as u8];
+            it.stack
+                .push_n(&[U256::zero(), U256::from(33), U256::zero(), U256::zero()]);
+            assert_eq!(it.run().err(), Some(err::Error::ExccedMaxInitCodeSize));
+        }
+    }
+
     #[test]
     fn test_op_mcopy() {
         let mut it = default_interpreter();
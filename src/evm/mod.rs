@@ -1,4 +1,5 @@
 pub mod common;
+pub mod eof;
 mod err;
 mod ext;
 pub mod extmock;
@@ -9,5 +10,7 @@ mod stack;
 
 pub use err::Error;
 pub use ext::DataProvider;
-pub use interpreter::{Context, Contract, Interpreter, InterpreterConf, InterpreterParams, InterpreterResult, Log};
+pub use interpreter::{
+    Context, Contract, Interpreter, InterpreterConf, InterpreterParams, InterpreterResult, Log, Spec,
+};
 pub use opcodes::OpCode;
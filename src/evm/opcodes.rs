@@ -54,6 +54,7 @@ pub enum OpCode {
     CHAINID = 0x46,
     SELFBALANCE = 0x47,
     BASEFEE = 0x48,
+    BLOBHASH = 0x49,
     BLOBBASEFEE = 0x4a,
     POP = 0x50,
     MLOAD = 0x51,
@@ -140,6 +141,11 @@ pub enum OpCode {
     LOG2 = 0xa2,
     LOG3 = 0xa3,
     LOG4 = 0xa4,
+    RJUMP = 0xe0,
+    RJUMPI = 0xe1,
+    RJUMPV = 0xe2,
+    CALLF = 0xe3,
+    RETF = 0xe4,
     CREATE = 0xf0,
     CALL = 0xf1,
     CALLCODE = 0xf2,
@@ -148,6 +154,7 @@ pub enum OpCode {
     CREATE2 = 0xf5,
     REVERT = 0xfd,
     STATICCALL = 0xfa,
+    INVALID = 0xfe,
     SELFDESTRUCT = 0xff,
 }
 
@@ -206,6 +213,7 @@ impl fmt::Display for OpCode {
             OpCode::CHAINID => write!(f, "CHAINID"),
             OpCode::SELFBALANCE => write!(f, "SELFBALANCE"),
             OpCode::BASEFEE => write!(f, "BASEFEE"),
+            OpCode::BLOBHASH => write!(f, "BLOBHASH"),
             OpCode::BLOBBASEFEE => write!(f, "BLOBBASEFEE"),
             OpCode::POP => write!(f, "POP"),
             OpCode::MLOAD => write!(f, "MLOAD"),
@@ -292,6 +300,11 @@ impl fmt::Display for OpCode {
             OpCode::LOG2 => write!(f, "LOG2"),
             OpCode::LOG3 => write!(f, "LOG3"),
             OpCode::LOG4 => write!(f, "LOG4"),
+            OpCode::RJUMP => write!(f, "RJUMP"),
+            OpCode::RJUMPI => write!(f, "RJUMPI"),
+            OpCode::RJUMPV => write!(f, "RJUMPV"),
+            OpCode::CALLF => write!(f, "CALLF"),
+            OpCode::RETF => write!(f, "RETF"),
             OpCode::CREATE => write!(f, "CREATE"),
             OpCode::CALL => write!(f, "CALL"),
             OpCode::CALLCODE => write!(f, "CALLCODE"),
@@ -300,6 +313,7 @@ impl fmt::Display for OpCode {
             OpCode::CREATE2 => write!(f, "CREATE2"),
             OpCode::REVERT => write!(f, "REVERT"),
             OpCode::STATICCALL => write!(f, "STATICCALL"),
+            OpCode::INVALID => write!(f, "INVALID"),
             OpCode::SELFDESTRUCT => write!(f, "SELFDESTRUCT"),
         }
     }
@@ -387,6 +401,7 @@ impl OpCode {
             0x46 => Some(OpCode::CHAINID),
             0x47 => Some(OpCode::SELFBALANCE),
             0x48 => Some(OpCode::BASEFEE),
+            0x49 => Some(OpCode::BLOBHASH),
             0x4a => Some(OpCode::BLOBBASEFEE),
             0x50 => Some(OpCode::POP),
             0x51 => Some(OpCode::MLOAD),
@@ -473,6 +488,11 @@ impl OpCode {
             0xa2 => Some(OpCode::LOG2),
             0xa3 => Some(OpCode::LOG3),
             0xa4 => Some(OpCode::LOG4),
+            0xe0 => Some(OpCode::RJUMP),
+            0xe1 => Some(OpCode::RJUMPI),
+            0xe2 => Some(OpCode::RJUMPV),
+            0xe3 => Some(OpCode::CALLF),
+            0xe4 => Some(OpCode::RETF),
             0xf0 => Some(OpCode::CREATE),
             0xf1 => Some(OpCode::CALL),
             0xf2 => Some(OpCode::CALLCODE),
@@ -481,6 +501,7 @@ impl OpCode {
             0xf5 => Some(OpCode::CREATE2),
             0xfd => Some(OpCode::REVERT),
             0xfa => Some(OpCode::STATICCALL),
+            0xfe => Some(OpCode::INVALID),
             0xff => Some(OpCode::SELFDESTRUCT),
             _ => None,
         }
@@ -540,6 +561,7 @@ impl OpCode {
             OpCode::CHAINID => GasPriceTier::VeryLow,
             OpCode::SELFBALANCE => GasPriceTier::Low,
             OpCode::BASEFEE => GasPriceTier::Base,
+            OpCode::BLOBHASH => GasPriceTier::VeryLow,
             OpCode::BLOBBASEFEE => GasPriceTier::Base,
             OpCode::POP => GasPriceTier::Base,
             OpCode::MLOAD => GasPriceTier::VeryLow,
@@ -626,12 +648,18 @@ impl OpCode {
             OpCode::LOG2 => GasPriceTier::Special,
             OpCode::LOG3 => GasPriceTier::Special,
             OpCode::LOG4 => GasPriceTier::Special,
+            OpCode::RJUMP => GasPriceTier::Base,
+            OpCode::RJUMPI => GasPriceTier::Special,
+            OpCode::RJUMPV => GasPriceTier::Special,
+            OpCode::CALLF => GasPriceTier::Low,
+            OpCode::RETF => GasPriceTier::VeryLow,
             OpCode::CREATE => GasPriceTier::Special,
             OpCode::CALL => GasPriceTier::Special,
             OpCode::CALLCODE => GasPriceTier::Special,
             OpCode::RETURN => GasPriceTier::Zero,
             OpCode::DELEGATECALL => GasPriceTier::Special,
             OpCode::STATICCALL => GasPriceTier::Special,
+            OpCode::INVALID => GasPriceTier::Zero,
             OpCode::SELFDESTRUCT => GasPriceTier::Special,
             OpCode::CREATE2 => GasPriceTier::Special,
             OpCode::REVERT => GasPriceTier::Zero,
@@ -692,6 +720,7 @@ impl OpCode {
             OpCode::CHAINID => 0,
             OpCode::SELFBALANCE => 0,
             OpCode::BASEFEE => 0,
+            OpCode::BLOBHASH => 1,
             OpCode::BLOBBASEFEE => 0,
             OpCode::POP => 1,
             OpCode::MLOAD => 1,
@@ -778,12 +807,18 @@ impl OpCode {
             OpCode::LOG2 => 4,
             OpCode::LOG3 => 5,
             OpCode::LOG4 => 6,
+            OpCode::RJUMP => 0,
+            OpCode::RJUMPI => 1,
+            OpCode::RJUMPV => 1,
+            OpCode::CALLF => 0, // Depends on the type of the called section
+            OpCode::RETF => 0,
             OpCode::CREATE => 3,
             OpCode::CALL => 7,
             OpCode::CALLCODE => 7,
             OpCode::RETURN => 2,
             OpCode::DELEGATECALL => 6,
             OpCode::STATICCALL => 6,
+            OpCode::INVALID => 0,
             OpCode::SELFDESTRUCT => 1,
             OpCode::CREATE2 => 4,
             OpCode::REVERT => 2,
@@ -844,6 +879,7 @@ impl OpCode {
             OpCode::CHAINID => 1,
             OpCode::SELFBALANCE => 1,
             OpCode::BASEFEE => 1,
+            OpCode::BLOBHASH => 1,
             OpCode::BLOBBASEFEE => 1,
             OpCode::POP => 0,
             OpCode::MLOAD => 1,
@@ -930,12 +966,18 @@ impl OpCode {
             OpCode::LOG2 => 0,
             OpCode::LOG3 => 0,
             OpCode::LOG4 => 0,
+            OpCode::RJUMP => 0,
+            OpCode::RJUMPI => 0,
+            OpCode::RJUMPV => 0,
+            OpCode::CALLF => 0, // Depends on the type of the called section
+            OpCode::RETF => 0,
             OpCode::CREATE => 1,
             OpCode::CALL => 1,
             OpCode::CALLCODE => 1,
             OpCode::RETURN => 0,
             OpCode::DELEGATECALL => 1,
             OpCode::STATICCALL => 1,
+            OpCode::INVALID => 0,
             OpCode::SELFDESTRUCT => 0,
             OpCode::CREATE2 => 1,
             OpCode::REVERT => 0,
@@ -996,6 +1038,7 @@ impl OpCode {
             OpCode::CHAINID => false,
             OpCode::SELFBALANCE => false,
             OpCode::BASEFEE => false,
+            OpCode::BLOBHASH => false,
             OpCode::BLOBBASEFEE => false,
             OpCode::POP => false,
             OpCode::MLOAD => false,
@@ -1082,12 +1125,18 @@ impl OpCode {
             OpCode::LOG2 => true,
             OpCode::LOG3 => true,
             OpCode::LOG4 => true,
+            OpCode::RJUMP => false,
+            OpCode::RJUMPI => false,
+            OpCode::RJUMPV => false,
+            OpCode::CALLF => false,
+            OpCode::RETF => false,
             OpCode::CREATE => true,
             OpCode::CALL => false,
             OpCode::CALLCODE => false,
             OpCode::RETURN => false,
             OpCode::DELEGATECALL => false,
             OpCode::STATICCALL => false,
+            OpCode::INVALID => false,
             OpCode::SELFDESTRUCT => true,
             OpCode::CREATE2 => true,
             OpCode::REVERT => false,
@@ -1,4 +1,7 @@
 use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::rc::Rc;
 use std::sync::Arc;
 
 use cita_trie::DB;
@@ -7,12 +10,13 @@ use evm::InterpreterParams;
 use hashbrown::{HashMap, HashSet};
 use log::debug;
 use rlp::RlpStream;
+use serde_derive::{Deserialize, Serialize};
 
 use crate::common;
 use crate::err;
 use crate::evm;
 use crate::native;
-use crate::state::{State, StateObjectInfo};
+use crate::state::{State, StateDiff, StateObjectInfo, WriteBatch};
 use cita_hasher::Hasher;
 
 /// BlockDataProvider provides functions to get block's hash from chain.
@@ -44,12 +48,29 @@ impl BlockDataProvider for BlockDataProviderMock {
     }
 }
 
-/// Store storages shared datas.
+/// A change of the substate, which is recorded in the journal to be undone.
+#[derive(Clone, Debug)]
+enum StoreChange {
+    Refund(u64), // The previous refund counter
+    Selfdestruct(Address),
+    Created(Address),
+    Touched(Address),
+    TransientStorage(Address, H256, H256), // The previous value
+}
+
+/// Store storages shared datas. It is the substate of a transaction, which is
+/// shared by all frames and checkpointed alongside `State::checkpoint`, so the
+/// changes made by a reverted frame are undone.
+///
+/// Logs are not kept here: they are carried by the result of a frame, which
+/// drops them when the frame reverts.
 #[derive(Clone, Default, Debug)]
 pub struct Store {
-    refund: HashMap<Address, u64>,                 // For record refunds
-    origin: HashMap<Address, HashMap<H256, H256>>, // For record origin value
-    selfdestruct: HashSet<Address>,                // For self destruct
+    refund: u64,                                              // Refund counter of the transaction
+    origin: HashMap<Address, HashMap<H256, H256>>,            // For record origin value
+    selfdestruct: HashSet<Address>,                           // For self destruct
+    created: HashSet<Address>,                                // Contracts created in the transaction. See: EIP 6780
+    transient_storage: HashMap<Address, HashMap<H256, H256>>, // See: EIP 1153
     // Field inused used for garbage collection.
     //
     // Test:
@@ -59,15 +80,77 @@ pub struct Store {
     inused: HashSet<Address>,
     evm_context: evm::Context,
     evm_cfg: evm::InterpreterConf,
+    // Addresses and storage keys touched during the transaction. It is shared
+    // by all frames, so reverted frames are recorded as well.
+    accessed: Option<Rc<RefCell<Accessed>>>,
+    // Consulted by CREATE and CALL of nested frames if present.
+    authorizer: Option<Arc<dyn Authorizer>>,
+    gas_permission_denied: u64, // Gas charged for a nested frame denied by the authorizer
+    journal: Vec<StoreChange>,
+    checkpoints: Vec<usize>, // Length of the journal at each checkpoint
 }
 
 impl Store {
-    /// Merge with sub store.
-    pub fn merge(&mut self, other: Arc<RefCell<Self>>) {
-        self.refund = other.borrow().refund.clone();
-        self.origin = other.borrow().origin.clone();
-        self.selfdestruct = other.borrow().selfdestruct.clone();
-        self.inused = other.borrow().inused.clone();
+    /// Create a new checkpoint.
+    pub fn checkpoint(&mut self) {
+        self.checkpoints.push(self.journal.len());
+    }
+
+    /// Keep the changes since the last checkpoint and discard it.
+    pub fn discard_checkpoint(&mut self) {
+        self.checkpoints.pop();
+        if self.checkpoints.is_empty() {
+            self.journal.clear();
+        }
+    }
+
+    /// Undo the changes since the last checkpoint and discard it.
+    pub fn revert_checkpoint(&mut self) {
+        let n = match self.checkpoints.pop() {
+            Some(n) => n,
+            None => return,
+        };
+        for change in self.journal.drain(n..).rev() {
+            match change {
+                StoreChange::Refund(v) => self.refund = v,
+                StoreChange::Selfdestruct(address) => {
+                    self.selfdestruct.remove(&address);
+                }
+                StoreChange::Created(address) => {
+                    self.created.remove(&address);
+                }
+                StoreChange::Touched(address) => {
+                    self.inused.remove(&address);
+                }
+                StoreChange::TransientStorage(address, key, value) => {
+                    self.transient_storage.entry(address).or_default().insert(key, value);
+                }
+            }
+        }
+    }
+
+    // Changes made out of any checkpoint are never reverted.
+    fn record(&mut self, change: StoreChange) {
+        if !self.checkpoints.is_empty() {
+            self.journal.push(change);
+        }
+    }
+
+    fn set_refund(&mut self, n: u64) {
+        self.record(StoreChange::Refund(self.refund));
+        self.refund = n;
+    }
+
+    fn insert_selfdestruct(&mut self, address: Address) {
+        if self.selfdestruct.insert(address) {
+            self.record(StoreChange::Selfdestruct(address));
+        }
+    }
+
+    fn insert_created(&mut self, address: Address) {
+        if self.created.insert(address) {
+            self.record(StoreChange::Created(address));
+        }
     }
 
     /// When a account has been read or write, record a log
@@ -76,10 +159,45 @@ impl Store {
         if address == Address::zero() {
             return;
         }
-        self.inused.insert(address);
+        if self.inused.insert(address) {
+            self.record(StoreChange::Touched(address));
+        }
+    }
+
+    fn get_transient_storage(&self, address: &Address, key: &H256) -> H256 {
+        self.transient_storage
+            .get(address)
+            .and_then(|account| account.get(key).cloned())
+            .unwrap_or_else(H256::zero)
+    }
+
+    fn set_transient_storage(&mut self, address: Address, key: H256, value: H256) {
+        let prev = self
+            .transient_storage
+            .entry(address)
+            .or_default()
+            .insert(key, value)
+            .unwrap_or_else(H256::zero);
+        self.record(StoreChange::TransientStorage(address, key, prev));
+    }
+
+    /// Record an address touched by the transaction.
+    pub fn access(&self, address: &Address) {
+        if let Some(accessed) = &self.accessed {
+            accessed.borrow_mut().entry(*address).or_default();
+        }
+    }
+
+    /// Record a storage key touched by the transaction.
+    pub fn access_storage(&self, address: &Address, key: &H256) {
+        if let Some(accessed) = &self.accessed {
+            accessed.borrow_mut().entry(*address).or_default().insert(*key);
+        }
     }
 }
 
+type Accessed = BTreeMap<Address, BTreeSet<H256>>;
+
 /// An implemention for evm::DataProvider
 pub struct DataProvider<B> {
     block_provider: Arc<dyn BlockDataProvider>,
@@ -96,6 +214,15 @@ impl<B: DB> DataProvider<B> {
             store,
         }
     }
+
+    // A nested frame denied by the authorizer is charged gas_permission_denied
+    // out of the gas forwarded to it, and the rest is given back.
+    fn permission_denied(&self, gas_limit: u64) -> evm::Error {
+        let gas = std::cmp::min(self.store.borrow().gas_permission_denied, gas_limit);
+        evm::Error::PermissionDenied {
+            gas_left: gas_limit - gas,
+        }
+    }
 }
 
 /// Returns new address created from address and nonce.
@@ -150,72 +277,341 @@ pub fn can_create<B: DB + 'static>(
     Ok(a.is_zero() && b.is_empty())
 }
 
-// There are two payment: fixed value for transcation and mutable value
-// for input data. If is_create, another G_CREATE gas required.
+// There are three payment: fixed value for transcation, mutable value for
+// input data and the entries of the access list. If is_create, another
+// tx_create gas and the initcode cost are required.
 //
-// gas_prepare = 21000 + (68 or 4 per byte) + (32000 if tx.to == 0)
-pub fn get_gas_prepare(request: &InterpreterParams) -> u64 {
+// gas_prepare = tx + (tx_data_zero or tx_data_non_zero per byte)
+//             + (tx_create + initcode_word per word if tx.to == 0)
+//             + access_list_address per address + access_list_storage_key per storage key
+pub fn get_gas_prepare(request: &InterpreterParams, access_list: &[AccessListItem], schedule: &GasSchedule) -> u64 {
     let mut gas_prepare: u64 = 0;
-    gas_prepare += G_TRANSACTION;
+    gas_prepare += schedule.tx;
     if request.is_create {
-        gas_prepare += G_CREATE
+        gas_prepare += schedule.tx_create;
+        gas_prepare += schedule.initcode_word * (request.input.len() as u64).div_ceil(32);
     }
     for i in &request.input {
         if i == &0u8 {
-            gas_prepare += G_TX_DATA_ZERO
+            gas_prepare += schedule.tx_data_zero
         } else {
-            gas_prepare += G_TX_DATA_NON_ZERO
+            gas_prepare += schedule.tx_data_non_zero
         }
     }
+    for item in access_list {
+        gas_prepare += schedule.access_list_address;
+        gas_prepare += schedule.access_list_storage_key * item.storage_keys.len() as u64;
+    }
     gas_prepare
 }
 
+// The minimal gas a transaction is charged for its input data, no matter
+// how much gas the execution uses. See: EIP 7623
+//
+// gas_floor = tx + tx_data_floor_per_token * (zero bytes + tx_data_tokens_per_non_zero * non-zero bytes)
+//
+// There is no floor if tx_data_floor_per_token is zero.
+pub fn get_gas_floor(request: &InterpreterParams, schedule: &GasSchedule) -> u64 {
+    if schedule.tx_data_floor_per_token == 0 {
+        return 0;
+    }
+    let tokens = request.input.iter().fold(0, |acc, i| {
+        if i == &0u8 {
+            acc + 1
+        } else {
+            acc + schedule.tx_data_tokens_per_non_zero
+        }
+    });
+    schedule.tx + schedule.tx_data_floor_per_token * tokens
+}
+
+// Approximates factor * e ** (numerator / denominator) using Taylor expansion.
+// See: EIP 4844
+fn fake_exponential(factor: U256, numerator: U256, denominator: U256) -> U256 {
+    let mut i = U256::one();
+    let mut output = U256::zero();
+    let mut numerator_accum = factor * denominator;
+    while !numerator_accum.is_zero() {
+        output = output.saturating_add(numerator_accum);
+        numerator_accum = match numerator_accum.checked_mul(numerator) {
+            Some(n) => n / (denominator * i),
+            None => return U256::max_value(),
+        };
+        i += U256::one();
+    }
+    output / denominator
+}
+
+/// Function get_blob_base_fee returns the price of blob gas for the given
+/// excess blob gas of the block. See: EIP 4844
+pub fn get_blob_base_fee(excess_blob_gas: u64) -> U256 {
+    fake_exponential(
+        U256::from(MIN_BLOB_BASE_FEE),
+        U256::from(excess_blob_gas),
+        U256::from(BLOB_BASE_FEE_UPDATE_FRACTION),
+    )
+}
+
 /// Function get_refund returns the real ammount to refund for a transaction.
 pub fn get_refund(store: Arc<RefCell<Store>>, request: &InterpreterParams, gas_left: u64) -> u64 {
-    let refunds_bound = match store.borrow().refund.get(&request.origin) {
-        Some(&data) => data,
-        None => 0u64,
-    };
-    // Get real ammount to refund
-    std::cmp::min(refunds_bound, (request.gas_limit - gas_left) >> 1)
+    let store = store.borrow();
+    // A quotient of zero disables the refund.
+    if store.evm_cfg.max_refund_quotient == 0 {
+        return 0;
+    }
+    // Get real ammount to refund, which is capped at a part of the gas used. See: EIP 3529
+    std::cmp::min(
+        store.refund,
+        (request.gas_limit - gas_left) / store.evm_cfg.max_refund_quotient,
+    )
+}
+
+// The transaction is charged at least gas_floor, after the refund is
+// applied. Returns the adjusted gas_left and refund. See: EIP 7623
+fn apply_gas_floor(gas_limit: u64, gas_left: u64, refund: u64, gas_floor: u64) -> (u64, u64) {
+    if gas_limit - gas_left - refund >= gas_floor {
+        (gas_left, refund)
+    } else {
+        (gas_limit - gas_floor, 0)
+    }
 }
 
 /// Liquidtion for a transaction.
 pub fn clear<B: DB + 'static>(
     state_provider: Arc<RefCell<State<B>>>,
     store: Arc<RefCell<Store>>,
+    fee_policy: &dyn FeePolicy,
     request: &InterpreterParams,
     gas_left: u64,
     refund: u64,
 ) -> Result<(), err::Error> {
-    state_provider
-        .borrow_mut()
-        .add_balance(&request.sender, request.gas_price * (gas_left + refund))?;
     state_provider.borrow_mut().add_balance(
-        &store.borrow().evm_context.coinbase,
-        request.gas_price * (request.gas_limit - gas_left - refund),
+        &fee_policy.payer(request),
+        fee_policy.refund(request, gas_left + refund),
     )?;
+    let evm_context = store.borrow().evm_context.clone();
+    let gas_used = request.gas_limit - gas_left - refund;
+    for (address, fee) in fee_policy.distribute(&evm_context, request, gas_used) {
+        state_provider.borrow_mut().add_balance(&address, fee)?;
+        store.borrow_mut().used(address);
+    }
     Ok(())
 }
 
+/// The economic model of transactions. `exec` consults it to prepay the gas
+/// limit before the execution, give back the gas left after it, and pay the
+/// fee of the gas used. The fee which is not distributed is burned.
+///
+/// It is skipped when `Config.check_balance` is false.
+pub trait FeePolicy: fmt::Debug + Send + Sync {
+    /// The account which pays for the gas.
+    fn payer(&self, request: &InterpreterParams) -> Address {
+        request.sender
+    }
+
+    /// The amount charged from the payer before the execution.
+    fn prepay(&self, request: &InterpreterParams) -> U256 {
+        request.gas_price * request.gas_limit
+    }
+
+    /// The amount given back to the payer for the gas left, refunds included.
+    fn refund(&self, request: &InterpreterParams, gas_left: u64) -> U256 {
+        request.gas_price * gas_left
+    }
+
+    /// The receivers of the fee of the gas used, refunds excluded.
+    fn distribute(
+        &self,
+        evm_context: &evm::Context,
+        request: &InterpreterParams,
+        gas_used: u64,
+    ) -> Vec<(Address, U256)>;
+}
+
+/// Permission checks of transactions, which restrict who can send
+/// transactions, deploy contracts and call specific contracts.
+///
+/// `exec` consults it for the transaction itself, and nested CREATE and CALL
+/// are checked as well when `Config.check_nested_permission` is true.
+pub trait Authorizer: fmt::Debug + Send + Sync {
+    /// Whether the sender is allowed to send transactions.
+    fn can_send(&self, _sender: &Address) -> bool {
+        true
+    }
+
+    /// Whether the sender is allowed to deploy contracts.
+    fn can_create(&self, _sender: &Address) -> bool {
+        true
+    }
+
+    /// Whether the sender is allowed to call the contract with the input.
+    fn can_call(&self, _sender: &Address, _contract: &Address, _input: &[u8]) -> bool {
+        true
+    }
+}
+
+/// Everything is allowed.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultAuthorizer;
+
+impl Authorizer for DefaultAuthorizer {}
+
+/// The sender pays for the gas, and the coinbase receives the whole fee.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultFeePolicy;
+
+impl FeePolicy for DefaultFeePolicy {
+    fn distribute(
+        &self,
+        evm_context: &evm::Context,
+        request: &InterpreterParams,
+        gas_used: u64,
+    ) -> Vec<(Address, U256)> {
+        vec![(evm_context.coinbase, request.gas_price * gas_used)]
+    }
+}
+
+/// Intrinsic gas of a transaction, which is paid before the execution.
+///
+/// The default schedule keeps CITA's behavior: input data is free and there
+/// is no floor. Use `istanbul`, `shanghai` or `prague` for the Ethereum costs.
+#[derive(Clone, Debug)]
+pub struct GasSchedule {
+    pub tx: u64,                          // Paid for every transaction
+    pub tx_create: u64,                   // Paid for contract create
+    pub tx_data_zero: u64,                // Paid for every zero byte of data or code for a transaction
+    pub tx_data_non_zero: u64,            // Paid for every non-zero byte of data or code for a transaction
+    pub tx_data_floor_per_token: u64,     // Floor price per token of input data. See: EIP 7623
+    pub tx_data_tokens_per_non_zero: u64, // Tokens counted for every non-zero byte. See: EIP 7623
+    pub initcode_word: u64,               // Paid for every word of initcode. See: EIP 3860
+    pub access_list_address: u64,         // Paid for every address in the access list. See: EIP 2930
+    pub access_list_storage_key: u64,     // Paid for every storage key in the access list. See: EIP 2930
+}
+
+impl Default for GasSchedule {
+    fn default() -> Self {
+        GasSchedule {
+            tx: 21000,
+            tx_create: 32000,
+            tx_data_zero: 0,
+            tx_data_non_zero: 0,
+            tx_data_floor_per_token: 0,
+            tx_data_tokens_per_non_zero: 4,
+            initcode_word: 0,
+            access_list_address: 2400,
+            access_list_storage_key: 1900,
+        }
+    }
+}
+
+impl GasSchedule {
+    /// Ethereum's costs before EIP 2028.
+    pub fn constantinople() -> Self {
+        GasSchedule {
+            tx_data_zero: 4,
+            tx_data_non_zero: 68,
+            ..Default::default()
+        }
+    }
+
+    /// Ethereum's costs with cheaper non-zero bytes. See: EIP 2028
+    pub fn istanbul() -> Self {
+        GasSchedule {
+            tx_data_non_zero: 16,
+            ..GasSchedule::constantinople()
+        }
+    }
+
+    /// Istanbul's costs with initcode metering. See: EIP 3860
+    pub fn shanghai() -> Self {
+        GasSchedule {
+            initcode_word: 2,
+            ..GasSchedule::istanbul()
+        }
+    }
+
+    /// Shanghai's costs with the calldata floor price. See: EIP 7623
+    pub fn prague() -> Self {
+        GasSchedule {
+            tx_data_floor_per_token: 10,
+            ..GasSchedule::shanghai()
+        }
+    }
+}
+
+/// How `exec` validates and updates the nonce of the sender.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NonceMode {
+    /// CITA's behavior. The nonce of tx is not checked and the nonce of the
+    /// sender is not increased, callers manage it by themselves. Contract
+    /// addresses are derived from the nonce in the state.
+    Cita,
+    /// Like `Cita`, but callers increase the nonce before `exec`, so the
+    /// nonce of tx must be one less than the nonce in the state.
+    CitaChecked,
+    /// The nonce of tx must equal the nonce in the state, which is increased
+    /// by `exec` once the transaction is valid, even if it reverts or fails.
+    /// A transaction denied by the `Authorizer` increases it only if it is
+    /// charged `gas_permission_denied`.
+    Strict,
+}
+
 /// Mutable configs in cita-vm's execution.
 #[derive(Clone, Debug)]
 pub struct Config {
     pub block_gas_limit: u64, // gas limit for a block.
-    pub check_nonce: bool,
+    // Quota an account can use in a block, enforced by `Executive`.
+    pub account_quota_limit: u64,
+    pub specific_quota_limits: HashMap<Address, u64>, // overrides of account_quota_limit.
+    pub nonce_mode: NonceMode,
     pub check_balance: bool,
+    pub gas_schedule: GasSchedule, // intrinsic gas of transactions.
+    pub evm_cfg: evm::InterpreterConf,
+    pub fee_policy: Arc<dyn FeePolicy>,
+    pub authorizer: Arc<dyn Authorizer>,
+    pub check_nested_permission: bool,
+    pub gas_permission_denied: u64, // gas charged for a transaction or nested frame without permission.
+    pub admin: Option<Address>,     // the only sender of amend transactions, None disables them.
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
             block_gas_limit: 8_000_000,
-            check_nonce: false,
+            account_quota_limit: u64::MAX,
+            specific_quota_limits: HashMap::new(),
+            nonce_mode: NonceMode::Cita,
             check_balance: true,
+            gas_schedule: GasSchedule::default(),
+            evm_cfg: get_interpreter_conf(),
+            fee_policy: Arc::new(DefaultFeePolicy),
+            authorizer: Arc::new(DefaultAuthorizer),
+            check_nested_permission: false,
+            gas_permission_denied: 0,
+            admin: None,
         }
     }
 }
 
+impl Config {
+    /// Returns the quota limit of the account in a block.
+    pub fn quota_limit(&self, address: &Address) -> u64 {
+        *self
+            .specific_quota_limits
+            .get(address)
+            .unwrap_or(&self.account_quota_limit)
+    }
+}
+
+/// Delete the empty accounts touched by the transaction, if it is enabled by
+/// `no_empty`. See: EIP 161
+fn kill_garbage<B: DB + 'static>(state_provider: Arc<RefCell<State<B>>>, store: Arc<RefCell<Store>>) {
+    let store = store.borrow();
+    if store.evm_cfg.no_empty {
+        state_provider.borrow_mut().kill_garbage(&store.inused);
+    }
+}
+
 /// Function call_pure enters into the specific contract with no check or checkpoints.
 fn call_pure<B: DB + 'static>(
     block_provider: Arc<dyn BlockDataProvider>,
@@ -226,6 +622,9 @@ fn call_pure<B: DB + 'static>(
     let evm_context = store.borrow().evm_context.clone();
     let evm_cfg = store.borrow().evm_cfg.clone();
     let evm_params = request.clone();
+    // Every call touches the receiver, even if no value is transferred. Calls
+    // to precompiled contracts are included. See: EIP 161
+    store.borrow_mut().used(request.receiver);
     let evm_data_provider = DataProvider::new(block_provider.clone(), state_provider.clone(), store);
     // Transfer value
     if !request.disable_transfer_value {
@@ -266,27 +665,24 @@ fn call<B: DB + 'static>(
         return Err(err::Error::NotEnoughBalance);
     }*/
     // Run
-    state_provider.borrow_mut().checkpoint();
-    let store_son = Arc::new(RefCell::new(store.borrow_mut().clone()));
-    let r = call_pure(
-        block_provider.clone(),
-        state_provider.clone(),
-        store_son.clone(),
-        request,
-    );
+    let checkpoint = state_provider.borrow_mut().checkpoint();
+    store.borrow_mut().checkpoint();
+    let r = call_pure(block_provider.clone(), state_provider.clone(), store.clone(), request);
     debug!("call result={:?}", r);
     match r {
         Ok(evm::InterpreterResult::Normal(output, gas_left, logs)) => {
-            state_provider.borrow_mut().discard_checkpoint();
-            store.borrow_mut().merge(store_son);
+            state_provider.borrow_mut().commit_to(checkpoint)?;
+            store.borrow_mut().discard_checkpoint();
             Ok(evm::InterpreterResult::Normal(output, gas_left, logs))
         }
         Ok(evm::InterpreterResult::Revert(output, gas_left)) => {
-            state_provider.borrow_mut().revert_checkpoint();
+            state_provider.borrow_mut().revert_to(checkpoint)?;
+            store.borrow_mut().revert_checkpoint();
             Ok(evm::InterpreterResult::Revert(output, gas_left))
         }
         Err(e) => {
-            state_provider.borrow_mut().revert_checkpoint();
+            state_provider.borrow_mut().revert_to(checkpoint)?;
+            store.borrow_mut().revert_checkpoint();
             Err(e)
         }
         _ => unimplemented!(),
@@ -317,15 +713,23 @@ fn create<B: DB + 'static>(
     if !can_create(state_provider.clone(), &address)? {
         return Err(err::Error::ContractAlreadyExist);
     }
+    if let Some(authorizer) = &store.borrow().authorizer {
+        if !authorizer.can_create(&request.sender) {
+            return Err(err::Error::NoContractPermission);
+        }
+    }
+    let no_empty = store.borrow().evm_cfg.no_empty;
     // Make a checkpoint here
-    state_provider.borrow_mut().checkpoint();
+    let checkpoint = state_provider.borrow_mut().checkpoint();
+    store.borrow_mut().checkpoint();
+    store.borrow_mut().insert_created(address);
     // Create a new contract
     let balance = state_provider.borrow_mut().balance(&address)?;
     state_provider.borrow_mut().new_contract(
         &address,
         balance,
         // The init nonce for a new contract is one, see above documents.
-        U256::zero(),
+        if no_empty { U256::one() } else { U256::zero() },
         // The init code should be none. Consider a situation: ContractA will create
         // ContractB with address 0x1ff...fff, but ContractB's init code contains some
         // op like "get code hash from 0x1ff..fff or get code size form 0x1ff...fff",
@@ -341,30 +745,31 @@ fn create<B: DB + 'static>(
         code_address: address,
         code_data: request.input.clone(),
     };
-    let r = call(block_provider.clone(), state_provider.clone(), store, &reqchan);
+    let (max_code_size, eip3541, eof) = {
+        let evm_cfg = &store.borrow().evm_cfg;
+        (evm_cfg.max_create_code_size, evm_cfg.eip3541, evm_cfg.eof)
+    };
+    let r = call(block_provider.clone(), state_provider.clone(), store.clone(), &reqchan);
+    let r = match r {
+        Ok(evm::InterpreterResult::Normal(output, gas_left, logs)) => {
+            check_deployed_code(&request.input, &output, gas_left, max_code_size, eip3541, eof)
+                .map(|gas_code_deposit| evm::InterpreterResult::Normal(output, gas_left - gas_code_deposit, logs))
+        }
+        r => r,
+    };
     match r {
         Ok(evm::InterpreterResult::Normal(output, gas_left, logs)) => {
-            // Ensure code size
-            if output.len() as u64 > MAX_CREATE_CODE_SIZE {
-                state_provider.borrow_mut().revert_checkpoint();
-                return Err(err::Error::ExccedMaxCodeSize);
-            }
-            // Pay every byte returnd from CREATE
-            let gas_code_deposit: u64 = G_CODE_DEPOSIT * output.len() as u64;
-            if gas_left < gas_code_deposit {
-                state_provider.borrow_mut().revert_checkpoint();
-                return Err(err::Error::Evm(evm::Error::OutOfGas));
-            }
-            let gas_left = gas_left - gas_code_deposit;
             state_provider.borrow_mut().set_code(&address, output.clone())?;
-            state_provider.borrow_mut().discard_checkpoint();
+            state_provider.borrow_mut().commit_to(checkpoint)?;
+            store.borrow_mut().discard_checkpoint();
             let r = Ok(evm::InterpreterResult::Create(output, gas_left, logs, address));
             debug!("create result={:?}", r);
             debug!("create gas_left={:?}", gas_left);
             r
         }
         Ok(evm::InterpreterResult::Revert(output, gas_left)) => {
-            state_provider.borrow_mut().revert_checkpoint();
+            state_provider.borrow_mut().revert_to(checkpoint)?;
+            store.borrow_mut().revert_checkpoint();
             let r = Ok(evm::InterpreterResult::Revert(output, gas_left));
             debug!("create gas_left={:?}", gas_left);
             debug!("create result={:?}", r);
@@ -372,22 +777,62 @@ fn create<B: DB + 'static>(
         }
         Err(e) => {
             debug!("create err={:?}", e);
-            state_provider.borrow_mut().revert_checkpoint();
+            state_provider.borrow_mut().revert_to(checkpoint)?;
+            store.borrow_mut().revert_checkpoint();
             Err(e)
         }
         _ => unimplemented!(),
     }
 }
 
-const G_TX_DATA_ZERO: u64 = 0; //4; // Paid for every zero byte of data or code for a transaction
-const G_TX_DATA_NON_ZERO: u64 = 0; //68; // Paid for every non-zero byte of data or code for a transaction
-const G_TRANSACTION: u64 = 21000; // Paid for every transaction
-const G_CREATE: u64 = 32000; // Paid for contract create
+/// Check the code returned by init code and return the gas to pay for depositing it.
+fn check_deployed_code(
+    init_code: &[u8],
+    output: &[u8],
+    gas_left: u64,
+    max_code_size: u64,
+    eip3541: bool,
+    eof: bool,
+) -> Result<u64, err::Error> {
+    // Ensure code size. See: EIP 170
+    if output.len() as u64 > max_code_size {
+        return Err(err::Error::ExccedMaxCodeSize);
+    }
+    // Ensure code format. EOF initcode must deploy valid EOF code, while
+    // legacy initcode must not deploy code starting with 0xEF.
+    // See: EIP 3540, EIP 3541
+    if eof && evm::eof::is_eof(init_code) {
+        evm::eof::validate(output)?;
+    } else if (eip3541 || eof) && output.first() == Some(&0xef) {
+        return Err(err::Error::Evm(evm::Error::InvalidCode));
+    }
+    // Pay every byte returnd from CREATE
+    let gas_code_deposit: u64 = G_CODE_DEPOSIT * output.len() as u64;
+    if gas_left < gas_code_deposit {
+        return Err(err::Error::Evm(evm::Error::OutOfGas));
+    }
+    Ok(gas_code_deposit)
+}
+
 const G_CODE_DEPOSIT: u64 = 200; // Paid per byte for a CREATE operation to succeed in placing code into state.
-const MAX_CREATE_CODE_SIZE: u64 = std::u64::MAX; // See: https://github.com/ethereum/EIPs/issues/659
+const GAS_PER_BLOB: u64 = 131_072; // Blob gas used by every blob. See: EIP 4844
+const MIN_BLOB_BASE_FEE: u64 = 1; // See: EIP 4844
+const BLOB_BASE_FEE_UPDATE_FRACTION: u64 = 3_338_477; // See: EIP 4844
+const VERSIONED_HASH_VERSION_KZG: u8 = 0x01; // The first byte of a blob versioned hash. See: EIP 4844
+
+/// An entry of the access list, which declares an address and the storage
+/// keys of it that the transaction plans to touch.
+///
+/// See: EIP 2930
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessListItem {
+    pub address: Address,
+    pub storage_keys: Vec<H256>,
+}
 
 /// Transaction struct.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct Transaction {
     pub from: Address,
     pub to: Option<Address>, // Some for call and None for create.
@@ -396,6 +841,10 @@ pub struct Transaction {
     pub gas_limit: u64,
     pub gas_price: U256,
     pub input: Vec<u8>,
+    // Paid before the execution at the prices of the gas schedule. See: EIP 2930
+    pub access_list: Vec<AccessListItem>,
+    pub max_fee_per_blob_gas: U256,
+    pub blob_versioned_hashes: Vec<H256>,
 }
 
 /// Reinterpret tx to interpreter params.
@@ -423,6 +872,7 @@ fn reinterpret_tx<B: DB + 'static>(tx: Transaction, state_provider: Arc<RefCell<
     request.value = tx.value;
     request.input = tx.input;
     request.nonce = tx.nonce;
+    request.blob_versioned_hashes = tx.blob_versioned_hashes;
     request
 }
 
@@ -434,51 +884,187 @@ pub fn exec<B: DB + 'static>(
     config: Config,
     tx: Transaction,
 ) -> Result<evm::InterpreterResult, err::Error> {
+    exec_with_salt(
+        block_provider,
+        state_provider,
+        evm_context,
+        config,
+        tx,
+        None,
+        None,
+        &mut 0,
+    )
+}
+
+/// Function exec_with_salt is the body of `exec`. If the transaction is a
+/// creation and salt is given, the contract address is derived like CREATE2.
+/// If accessed is given, every touched address and storage key is recorded
+/// into it. gas_used is set to the gas charged once the transaction is
+/// settled, and left untouched if it is rejected before.
+#[allow(clippy::too_many_arguments)]
+fn exec_with_salt<B: DB + 'static>(
+    block_provider: Arc<dyn BlockDataProvider>,
+    state_provider: Arc<RefCell<State<B>>>,
+    evm_context: evm::Context,
+    config: Config,
+    tx: Transaction,
+    salt: Option<H256>,
+    accessed: Option<Rc<RefCell<Accessed>>>,
+    gas_used: &mut u64,
+) -> Result<evm::InterpreterResult, err::Error> {
+    let max_fee_per_blob_gas = tx.max_fee_per_blob_gas;
+    let access_list = tx.access_list.clone();
     let request = &mut reinterpret_tx(tx, state_provider.clone());
+    request.blob_base_fee = get_blob_base_fee(evm_context.excess_blob_gas);
     // Ensure gas < block_gas_limit
 
     /* TODO : this judgement need be reconsider
-     fi config.block_gas_limit > G_TRANSACTION && request.gas_limit > config.block_gas_limit {
+     fi config.block_gas_limit > config.gas_schedule.tx && request.gas_limit > config.block_gas_limit {
         return Err(err::Error::ExccedMaxBlockGasLimit);
     }
     */
 
-    if config.check_nonce {
-        // Ensure nonce
-        if request.nonce + 1 != state_provider.borrow_mut().nonce(&request.sender)? {
-            return Err(err::Error::InvalidNonce);
+    // Ensure nonce
+    let nonce = state_provider.borrow_mut().nonce(&request.sender)?;
+    match config.nonce_mode {
+        NonceMode::Cita => request.nonce = nonce,
+        NonceMode::CitaChecked => {
+            if request.nonce + 1 != nonce {
+                return Err(err::Error::InvalidNonce {
+                    expected: nonce.saturating_sub(U256::one()),
+                    actual: request.nonce,
+                });
+            }
+        }
+        NonceMode::Strict => {
+            if request.nonce != nonce {
+                return Err(err::Error::InvalidNonce {
+                    expected: nonce,
+                    actual: request.nonce,
+                });
+            }
         }
-    } else {
-        request.nonce = state_provider.borrow_mut().nonce(&request.sender)?;
+    }
+    // Ensure initcode size. See: EIP 3860
+    if request.is_create && request.input.len() as u64 > config.evm_cfg.max_initcode_size {
+        return Err(err::Error::ExccedMaxInitCodeSize);
     }
     // Ensure gas
-    let gas_prepare = get_gas_prepare(request);
-    if request.gas_limit < gas_prepare {
+    let gas_prepare = get_gas_prepare(request, &access_list, &config.gas_schedule);
+    let gas_floor = get_gas_floor(request, &config.gas_schedule);
+    if request.gas_limit < std::cmp::max(gas_prepare, gas_floor) {
         return Err(err::Error::NotEnoughBaseGas);
     }
 
-    // Ensure value
-    if config.check_balance {
-        let gas_prepay = request.gas_price * request.gas_limit;
-        if state_provider.borrow_mut().balance(&request.sender)? < gas_prepay + request.value {
-            return Err(err::Error::NotEnoughBalance);
+    // Ensure blobs. See: EIP 4844
+    let blob_gas = GAS_PER_BLOB * request.blob_versioned_hashes.len() as u64;
+    if !request.blob_versioned_hashes.is_empty() {
+        if request.is_create
+            || request
+                .blob_versioned_hashes
+                .iter()
+                .any(|h| h[0] != VERSIONED_HASH_VERSION_KZG)
+        {
+            return Err(err::Error::InvalidBlobTransaction);
+        }
+        if max_fee_per_blob_gas < request.blob_base_fee {
+            return Err(err::Error::BlobGasPriceTooLow);
         }
-        // Pay intrinsic gas
-        state_provider.borrow_mut().sub_balance(&request.sender, gas_prepay)?;
     }
 
-    // Increament the nonce for the next transaction
-    // Comment for inc_nonce out of this exec
-    // state_provider.borrow_mut().inc_nonce(&request.sender)?;
-
     // Init the store for the transaction
     let store = Store {
-        evm_cfg: get_interpreter_conf(),
+        evm_cfg: config.evm_cfg.clone(),
         evm_context,
+        accessed,
+        authorizer: if config.check_nested_permission {
+            Some(config.authorizer.clone())
+        } else {
+            None
+        },
+        gas_permission_denied: config.gas_permission_denied,
         ..Default::default()
     };
     //store.used(request.receiver);
     let store = Arc::new(RefCell::new(store));
+    // Ensure permission. It is checked before anything is paid, so a
+    // transaction which is not permitted is charged gas_permission_denied
+    // only, and never the blob gas.
+    let permission = if !config.authorizer.can_send(&request.sender) {
+        Err(err::Error::NoTransactionPermission)
+    } else if request.is_create && !config.authorizer.can_create(&request.sender) {
+        Err(err::Error::NoContractPermission)
+    } else if !request.is_create
+        && !config
+            .authorizer
+            .can_call(&request.sender, &request.receiver, &request.input)
+    {
+        Err(err::Error::NoCallPermission(request.receiver))
+    } else {
+        Ok(())
+    };
+    if let Err(e) = permission {
+        // A transaction which is not permitted consumes its nonce only if it
+        // is charged, so a free rejection leaves the sender untouched. A
+        // payer who cannot prepay the gas is not charged either.
+        if config.check_balance && config.gas_permission_denied > 0 {
+            let payer = config.fee_policy.payer(request);
+            let gas_prepay = config.fee_policy.prepay(request);
+            if state_provider.borrow_mut().balance(&payer)? < gas_prepay {
+                return Err(e);
+            }
+            state_provider.borrow_mut().sub_balance(&payer, gas_prepay)?;
+            if config.nonce_mode == NonceMode::Strict {
+                state_provider.borrow_mut().inc_nonce(&request.sender)?;
+            }
+            let gas_left = request.gas_limit - std::cmp::min(config.gas_permission_denied, request.gas_limit);
+            *gas_used = request.gas_limit - gas_left;
+            clear(
+                state_provider.clone(),
+                store.clone(),
+                config.fee_policy.as_ref(),
+                request,
+                gas_left,
+                0,
+            )?;
+        }
+        return Err(e);
+    }
+
+    // Ensure value
+    if config.check_balance {
+        let payer = config.fee_policy.payer(request);
+        let gas_prepay = config.fee_policy.prepay(request);
+        // No balance covers an amount which overflows
+        let sender_prepay = max_fee_per_blob_gas
+            .checked_mul(U256::from(blob_gas))
+            .and_then(|blob_gas_prepay| blob_gas_prepay.checked_add(request.value))
+            .ok_or(err::Error::NotEnoughBalance)?;
+        if payer == request.sender {
+            let prepay = gas_prepay
+                .checked_add(sender_prepay)
+                .ok_or(err::Error::NotEnoughBalance)?;
+            if state_provider.borrow_mut().balance(&request.sender)? < prepay {
+                return Err(err::Error::NotEnoughBalance);
+            }
+        } else if state_provider.borrow_mut().balance(&payer)? < gas_prepay
+            || state_provider.borrow_mut().balance(&request.sender)? < sender_prepay
+        {
+            return Err(err::Error::NotEnoughBalance);
+        }
+        // Pay intrinsic gas
+        state_provider.borrow_mut().sub_balance(&payer, gas_prepay)?;
+        // Pay blob gas, which is burned and never refunded
+        state_provider
+            .borrow_mut()
+            .sub_balance(&request.sender, request.blob_base_fee * blob_gas)?;
+    }
+
+    // Increament the nonce for the next transaction. It is out of the
+    // checkpoints, so it is kept even if the transaction reverts or fails.
+    if config.nonce_mode == NonceMode::Strict {
+        state_provider.borrow_mut().inc_nonce(&request.sender)?;
+    }
     // Create a sub request
     let mut reqchan = request.clone();
     reqchan.gas_limit = request.gas_limit - gas_prepare;
@@ -486,12 +1072,19 @@ pub fn exec<B: DB + 'static>(
         reqchan.disable_transfer_value = true;
     }
     let r = if request.is_create {
+        let create_kind = match salt {
+            Some(salt) => {
+                reqchan.extra = salt;
+                CreateKind::FromSaltAndCodeHash
+            }
+            None => CreateKind::FromAddressAndNonce,
+        };
         create(
             block_provider.clone(),
             state_provider.clone(),
             store.clone(),
             &reqchan,
-            CreateKind::FromAddressAndNonce,
+            create_kind,
         )
     } else {
         call(block_provider.clone(), state_provider.clone(), store.clone(), &reqchan)
@@ -499,42 +1092,79 @@ pub fn exec<B: DB + 'static>(
     // Finalize
     match r {
         Ok(evm::InterpreterResult::Normal(output, gas_left, logs)) => {
+            let refund = get_refund(store.clone(), request, gas_left);
+            let (gas_left, refund) = apply_gas_floor(request.gas_limit, gas_left, refund, gas_floor);
+            *gas_used = request.gas_limit - gas_left;
+
             if config.check_balance {
-                let refund = get_refund(store.clone(), request, gas_left);
-                clear(state_provider.clone(), store.clone(), request, gas_left, refund)?;
+                clear(
+                    state_provider.clone(),
+                    store.clone(),
+                    config.fee_policy.as_ref(),
+                    request,
+                    gas_left,
+                    refund,
+                )?;
             }
             // Handle self destruct: Kill it.
             // Note: must after ends of the transaction.
             for e in store.borrow_mut().selfdestruct.drain() {
                 state_provider.borrow_mut().kill_contract(&e)
             }
-            state_provider.borrow_mut().kill_garbage(&store.borrow().inused.clone());
+            kill_garbage(state_provider.clone(), store.clone());
             Ok(evm::InterpreterResult::Normal(output, gas_left, logs))
         }
         Ok(evm::InterpreterResult::Revert(output, gas_left)) => {
+            let (gas_left, _) = apply_gas_floor(request.gas_limit, gas_left, 0, gas_floor);
+            *gas_used = request.gas_limit - gas_left;
             if config.check_balance {
-                clear(state_provider.clone(), store.clone(), request, gas_left, 0)?;
+                clear(
+                    state_provider.clone(),
+                    store.clone(),
+                    config.fee_policy.as_ref(),
+                    request,
+                    gas_left,
+                    0,
+                )?;
             }
-            state_provider.borrow_mut().kill_garbage(&store.borrow().inused.clone());
+            kill_garbage(state_provider.clone(), store.clone());
             Ok(evm::InterpreterResult::Revert(output, gas_left))
         }
         Ok(evm::InterpreterResult::Create(output, gas_left, logs, addr)) => {
+            let refund = get_refund(store.clone(), request, gas_left);
+            let (gas_left, refund) = apply_gas_floor(request.gas_limit, gas_left, refund, gas_floor);
+            *gas_used = request.gas_limit - gas_left;
+
             if config.check_balance {
-                let refund = get_refund(store.clone(), request, gas_left);
-                clear(state_provider.clone(), store.clone(), request, gas_left, refund)?;
+                clear(
+                    state_provider.clone(),
+                    store.clone(),
+                    config.fee_policy.as_ref(),
+                    request,
+                    gas_left,
+                    refund,
+                )?;
             }
             for e in store.borrow_mut().selfdestruct.drain() {
                 state_provider.borrow_mut().kill_contract(&e)
             }
-            state_provider.borrow_mut().kill_garbage(&store.borrow().inused.clone());
+            kill_garbage(state_provider.clone(), store.clone());
             Ok(evm::InterpreterResult::Create(output, gas_left, logs, addr))
         }
         Err(e) => {
             // When error, coinbase eats all gas as it's price, yummy.
+            *gas_used = request.gas_limit;
             if config.check_balance {
-                clear(state_provider.clone(), store.clone(), request, 0, 0)?;
+                clear(
+                    state_provider.clone(),
+                    store.clone(),
+                    config.fee_policy.as_ref(),
+                    request,
+                    0,
+                    0,
+                )?;
             }
-            state_provider.borrow_mut().kill_garbage(&store.borrow().inused.clone());
+            kill_garbage(state_provider.clone(), store.clone());
             Err(e)
         }
     }
@@ -559,10 +1189,11 @@ pub fn exec_static<B: DB + 'static>(
         return Err(err::Error::CreateInStaticCall);
     }
     let mut request = reinterpret_tx(tx, state_provider.clone());
+    request.blob_base_fee = get_blob_base_fee(evm_context.excess_blob_gas);
     request.read_only = true;
     request.disable_transfer_value = true;
     let store = Store {
-        evm_cfg: get_interpreter_conf(),
+        evm_cfg: config.evm_cfg.clone(),
         evm_context,
         ..Default::default()
     };
@@ -570,10 +1201,264 @@ pub fn exec_static<B: DB + 'static>(
     call_pure(block_provider.clone(), state_provider, store, &request)
 }
 
+/// The outcome of a contract deployment executed by `exec_dry_run_create`.
+#[derive(Clone, Debug)]
+pub struct DryRunCreate {
+    pub address: Address, // The would-be contract address
+    pub code: Vec<u8>,    // The runtime code returned by the init code
+    pub gas_used: u64,    // Gas used before refunds, intrinsic gas included
+    pub logs: Vec<evm::Log>,
+}
+
+/// Preview a contract deployment without persisting anything.
+///
+/// The transaction goes through the same path as `exec`, so nonce, balance
+/// and gas checks behave exactly the same, but everything runs inside a
+/// checkpoint that is always reverted. When `salt` is given the address is
+/// derived like CREATE2, otherwise from the sender and its nonce.
+pub fn exec_dry_run_create<B: DB + 'static>(
+    block_provider: Arc<dyn BlockDataProvider>,
+    state_provider: Arc<RefCell<State<B>>>,
+    evm_context: evm::Context,
+    config: Config,
+    tx: Transaction,
+    salt: Option<H256>,
+) -> Result<DryRunCreate, err::Error> {
+    if tx.to.is_some() {
+        return Err(err::Error::CallInDryRunCreate);
+    }
+    let gas_limit = tx.gas_limit;
+    let checkpoint = state_provider.borrow_mut().checkpoint();
+    let r = exec_with_salt(
+        block_provider,
+        state_provider.clone(),
+        evm_context,
+        config,
+        tx,
+        salt,
+        None,
+        &mut 0,
+    );
+    state_provider.borrow_mut().revert_to(checkpoint)?;
+    match r? {
+        evm::InterpreterResult::Create(code, gas_left, logs, address) => Ok(DryRunCreate {
+            address,
+            code,
+            gas_used: gas_limit - gas_left,
+            logs,
+        }),
+        evm::InterpreterResult::Revert(output, _) => Err(err::Error::Reverted(output)),
+        evm::InterpreterResult::Normal(..) => unreachable!("creation never returns a normal result"),
+    }
+}
+
+/// The outcome of `exec_create_access_list`.
+#[derive(Clone, Debug)]
+pub struct CreateAccessList {
+    pub access_list: Vec<AccessListItem>,
+    pub gas_used: u64,                     // Gas used with the generated access list attached
+    pub gas_used_without_access_list: u64, // Gas used with an empty access list
+}
+
+// The generated access list might change the execution path(for example,
+// by the GAS opcode), so it is regenerated until it is stable, but at most
+// MAX_ACCESS_LIST_ROUNDS times.
+const MAX_ACCESS_LIST_ROUNDS: usize = 8;
+
+/// Generate the access list for a transaction, like `eth_createAccessList`.
+///
+/// The transaction is executed in a checkpoint that is always reverted, and
+/// every address and storage key touched through `evm::DataProvider` is
+/// recorded. The sender, the recipient(or the created contract) and the
+/// precompiled contracts are left out, as EIP 2930 warms them anyway. The
+/// access list in tx is ignored.
+pub fn exec_create_access_list<B: DB + 'static>(
+    block_provider: Arc<dyn BlockDataProvider>,
+    state_provider: Arc<RefCell<State<B>>>,
+    evm_context: evm::Context,
+    config: Config,
+    tx: Transaction,
+) -> Result<CreateAccessList, err::Error> {
+    let recipient = match tx.to {
+        Some(data) => data,
+        None => {
+            let nonce = match config.nonce_mode {
+                NonceMode::Cita => state_provider.borrow_mut().nonce(&tx.from)?,
+                NonceMode::CitaChecked | NonceMode::Strict => tx.nonce,
+            };
+            create_address_from_address_and_nonce(&tx.from, &nonce)
+        }
+    };
+    let sender = tx.from;
+    let excluded = |address: &Address| address == &sender || address == &recipient || native::contains(address);
+    let run = |tx: Transaction| -> Result<(u64, Vec<AccessListItem>), err::Error> {
+        let accessed = Rc::new(RefCell::new(Accessed::new()));
+        let mut gas_used = 0;
+        let checkpoint = state_provider.borrow_mut().checkpoint();
+        let r = exec_with_salt(
+            block_provider.clone(),
+            state_provider.clone(),
+            evm_context.clone(),
+            config.clone(),
+            tx,
+            None,
+            Some(accessed.clone()),
+            &mut gas_used,
+        );
+        state_provider.borrow_mut().revert_to(checkpoint)?;
+        r?;
+        let access_list = accessed
+            .borrow()
+            .iter()
+            .filter(|(address, _)| !excluded(address))
+            .map(|(address, keys)| AccessListItem {
+                address: *address,
+                storage_keys: keys.iter().cloned().collect(),
+            })
+            .collect();
+        Ok((gas_used, access_list))
+    };
+
+    let mut tx = tx;
+    tx.access_list = vec![];
+    let (gas_used_without_access_list, mut access_list) = run(tx.clone())?;
+    let mut rounds = 0;
+    let gas_used = loop {
+        tx.access_list = access_list.clone();
+        let (gas_used, next) = run(tx.clone())?;
+        rounds += 1;
+        if next == access_list || rounds == MAX_ACCESS_LIST_ROUNDS {
+            break gas_used;
+        }
+        access_list = next;
+    };
+    Ok(CreateAccessList {
+        access_list,
+        gas_used,
+        gas_used_without_access_list,
+    })
+}
+
+/// The outcome of `exec_with_state_diff`.
+#[derive(Debug)]
+pub struct ExecStateDiff {
+    pub result: Result<evm::InterpreterResult, err::Error>,
+    pub state_diff: StateDiff, // Empty if the transaction is rejected
+}
+
+/// Execute the transaction like `exec`, and collect the changes it makes to
+/// the state. Changes of failed transactions, such as the gas fee, are
+/// collected as well.
+pub fn exec_with_state_diff<B: DB + 'static>(
+    block_provider: Arc<dyn BlockDataProvider>,
+    state_provider: Arc<RefCell<State<B>>>,
+    evm_context: evm::Context,
+    config: Config,
+    tx: Transaction,
+) -> Result<ExecStateDiff, err::Error> {
+    // Changes are only journaled inside a checkpoint, so one is taken for the
+    // diff, and kept whatever the result is.
+    let checkpoint = state_provider.borrow_mut().checkpoint();
+    let result = exec(block_provider, state_provider.clone(), evm_context, config, tx);
+    let state_diff = state_provider.borrow().diff_since(checkpoint);
+    state_provider.borrow_mut().commit_to(checkpoint)?;
+    Ok(ExecStateDiff {
+        result,
+        state_diff: state_diff?,
+    })
+}
+
+/// A privileged change of the state, which lets the administrator patch
+/// accounts after a governance vote.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Amend {
+    Code(Address, Vec<u8>),
+    Abi(Address, Vec<u8>),
+    Storage(Address, H256, H256), // Address, key and value
+    Balance(Address, U256),       // The new balance, not a delta
+}
+
+/// The receipt of an amend transaction.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AmendReceipt {
+    pub from: Address,
+    pub amends: Vec<Amend>,
+    pub previous: Vec<Amend>, // Values replaced by each amend, in the same order
+}
+
+/// Apply amends to the state. The sender must be `Config.admin`, and the
+/// amends are applied all or nothing.
+pub fn exec_amend<B: DB + 'static>(
+    state_provider: Arc<RefCell<State<B>>>,
+    config: Config,
+    from: Address,
+    amends: Vec<Amend>,
+) -> Result<AmendReceipt, err::Error> {
+    if config.admin != Some(from) {
+        return Err(err::Error::NoAmendPermission);
+    }
+    let checkpoint = state_provider.borrow_mut().checkpoint();
+    let r = amends
+        .iter()
+        .map(|amend| apply_amend(state_provider.clone(), &config, amend))
+        .collect::<Result<Vec<Amend>, err::Error>>();
+    match r {
+        Ok(previous) => {
+            state_provider.borrow_mut().commit_to(checkpoint)?;
+            Ok(AmendReceipt { from, amends, previous })
+        }
+        Err(e) => {
+            state_provider.borrow_mut().revert_to(checkpoint)?;
+            Err(e)
+        }
+    }
+}
+
+/// Apply an amend and return the value it replaces.
+fn apply_amend<B: DB + 'static>(
+    state_provider: Arc<RefCell<State<B>>>,
+    config: &Config,
+    amend: &Amend,
+) -> Result<Amend, err::Error> {
+    let mut state = state_provider.borrow_mut();
+    let previous = match amend {
+        Amend::Code(address, code) => {
+            if code.len() as u64 > config.evm_cfg.max_create_code_size {
+                return Err(err::Error::ExccedMaxCodeSize);
+            }
+            let previous = Amend::Code(*address, state.code(address)?);
+            state.set_code(address, code.clone())?;
+            previous
+        }
+        Amend::Abi(address, abi) => {
+            let previous = Amend::Abi(*address, state.abi(address)?);
+            state.set_abi(address, abi.clone())?;
+            previous
+        }
+        Amend::Storage(address, key, value) => {
+            let previous = Amend::Storage(*address, *key, state.get_storage(address, key)?);
+            state.set_storage(address, *key, *value)?;
+            previous
+        }
+        Amend::Balance(address, balance) => {
+            let previous = state.balance(address)?;
+            if *balance > previous {
+                state.add_balance(address, *balance - previous)?;
+            } else {
+                state.sub_balance(address, previous - *balance)?;
+            }
+            Amend::Balance(*address, previous)
+        }
+    };
+    Ok(previous)
+}
+
 pub struct Executive<B> {
     pub block_provider: Arc<dyn BlockDataProvider>,
     pub state_provider: Arc<RefCell<State<B>>>,
     pub config: Config,
+    // Quota used by each sender in current block.
+    quota_used: RefCell<HashMap<Address, u64>>,
 }
 
 impl<B: DB + 'static> Executive<B> {
@@ -582,17 +1467,43 @@ impl<B: DB + 'static> Executive<B> {
             block_provider,
             state_provider: Arc::new(RefCell::new(state_provider)),
             config,
+            quota_used: RefCell::new(HashMap::new()),
         }
     }
 
+    /// Returns the quota used by the account in current block.
+    pub fn quota_used(&self, address: &Address) -> u64 {
+        self.quota_used.borrow().get(address).cloned().unwrap_or(0)
+    }
+
+    /// Forgets the quota used by accounts. Call it at the beginning of a block.
+    pub fn reset_quota_used(&self) {
+        self.quota_used.borrow_mut().clear();
+    }
+
+    /// Execute the transaction, and account its quota to the sender. The
+    /// transaction is rejected if its gas limit exceeds the quota left.
     pub fn exec(&self, evm_context: evm::Context, tx: Transaction) -> Result<evm::InterpreterResult, err::Error> {
-        exec(
+        let sender = tx.from;
+        let gas_limit = tx.gas_limit;
+        let quota_used = self.quota_used(&sender);
+        if self.config.quota_limit(&sender).saturating_sub(quota_used) < gas_limit {
+            return Err(err::Error::ExccedAccountQuotaLimit);
+        }
+        // A transaction rejected before execution is charged nothing.
+        let mut gas_used = 0;
+        let r = exec_with_salt(
             self.block_provider.clone(),
             self.state_provider.clone(),
             evm_context,
             self.config.clone(),
             tx,
-        )
+            None,
+            None,
+            &mut gas_used,
+        );
+        self.quota_used.borrow_mut().insert(sender, quota_used + gas_used);
+        r
 
         // Bellow is saved for jsondata test
         /*let coinbase = evm_context.coinbase;
@@ -606,9 +1517,9 @@ impl<B: DB + 'static> Executive<B> {
         match exec_result {
             Err(err::Error::ExccedMaxBlockGasLimit)
             | Err(err::Error::NotEnoughBaseGas)
-            | Err(err::Error::InvalidNonce)
+            | Err(err::Error::InvalidNonce { .. })
             | Err(err::Error::NotEnoughBalance) => {
-                let balance = tx.gas_price * G_TRANSACTION;
+                let balance = tx.gas_price * self.config.gas_schedule.tx;
                 let account_balance = self.state_provider.borrow_mut().balance(&tx.from)?;
                 let real = {
                     if balance > account_balance {
@@ -645,48 +1556,97 @@ impl<B: DB + 'static> Executive<B> {
         )
     }
 
+    pub fn exec_dry_run_create(
+        &self,
+        evm_context: evm::Context,
+        tx: Transaction,
+        salt: Option<H256>,
+    ) -> Result<DryRunCreate, err::Error> {
+        exec_dry_run_create(
+            self.block_provider.clone(),
+            self.state_provider.clone(),
+            evm_context,
+            self.config.clone(),
+            tx,
+            salt,
+        )
+    }
+
+    pub fn exec_create_access_list(
+        &self,
+        evm_context: evm::Context,
+        tx: Transaction,
+    ) -> Result<CreateAccessList, err::Error> {
+        exec_create_access_list(
+            self.block_provider.clone(),
+            self.state_provider.clone(),
+            evm_context,
+            self.config.clone(),
+            tx,
+        )
+    }
+
+    pub fn exec_with_state_diff(
+        &self,
+        evm_context: evm::Context,
+        tx: Transaction,
+    ) -> Result<ExecStateDiff, err::Error> {
+        exec_with_state_diff(
+            self.block_provider.clone(),
+            self.state_provider.clone(),
+            evm_context,
+            self.config.clone(),
+            tx,
+        )
+    }
+
+    pub fn exec_amend(&self, from: Address, amends: Vec<Amend>) -> Result<AmendReceipt, err::Error> {
+        exec_amend(self.state_provider.clone(), self.config.clone(), from, amends)
+    }
+
     pub fn commit(&self) -> Result<H256, err::Error> {
         self.state_provider.borrow_mut().commit()?;
         Ok(self.state_provider.borrow_mut().root)
     }
+
+    pub fn commit_batch(&self) -> Result<WriteBatch, err::Error> {
+        Ok(self.state_provider.borrow_mut().commit_batch()?)
+    }
 }
 
 impl<B: DB + 'static> evm::DataProvider for DataProvider<B> {
     fn get_balance(&self, address: &Address) -> U256 {
+        self.store.borrow().access(address);
         self.state_provider
             .borrow_mut()
             .balance(address)
             .unwrap_or_else(|_| U256::zero())
     }
 
-    fn add_refund(&mut self, address: &Address, n: u64) {
-        self.store
-            .borrow_mut()
-            .refund
-            .entry(*address)
-            .and_modify(|v| *v += n)
-            .or_insert(n);
+    fn add_refund(&mut self, n: u64) {
+        let mut store = self.store.borrow_mut();
+        let refund = store.refund + n;
+        store.set_refund(refund);
     }
 
-    fn sub_refund(&mut self, address: &Address, n: u64) {
-        debug!("ext.sub_refund {:?} {}", address, n);
-        self.store
-            .borrow_mut()
-            .refund
-            .entry(*address)
-            .and_modify(|v| *v -= n)
-            .or_insert(n);
+    fn sub_refund(&mut self, n: u64) {
+        debug!("ext.sub_refund {}", n);
+        let mut store = self.store.borrow_mut();
+        let refund = store.refund.saturating_sub(n);
+        store.set_refund(refund);
     }
 
-    fn get_refund(&self, address: &Address) -> u64 {
-        self.store.borrow_mut().refund.get(address).map_or(0, |v| *v)
+    fn get_refund(&self) -> u64 {
+        self.store.borrow().refund
     }
 
     fn get_code_size(&self, address: &Address) -> u64 {
+        self.store.borrow().access(address);
         self.state_provider.borrow_mut().code_size(address).unwrap_or(0) as u64
     }
 
     fn get_code(&self, address: &Address) -> Vec<u8> {
+        self.store.borrow().access(address);
         self.state_provider
             .borrow_mut()
             .code(address)
@@ -694,6 +1654,7 @@ impl<B: DB + 'static> evm::DataProvider for DataProvider<B> {
     }
 
     fn get_code_hash(&self, address: &Address) -> H256 {
+        self.store.borrow().access(address);
         self.state_provider
             .borrow_mut()
             .code_hash(address)
@@ -705,6 +1666,7 @@ impl<B: DB + 'static> evm::DataProvider for DataProvider<B> {
     }
 
     fn get_storage(&self, address: &Address, key: &H256) -> H256 {
+        self.store.borrow().access_storage(address, key);
         self.state_provider
             .borrow_mut()
             .get_storage(address, key)
@@ -726,12 +1688,16 @@ impl<B: DB + 'static> evm::DataProvider for DataProvider<B> {
     }
 
     fn get_storage_origin(&self, address: &Address, key: &H256) -> H256 {
+        self.store.borrow().access_storage(address, key);
         //self.store.borrow_mut().used(address.clone());
-        match self.store.borrow_mut().origin.get(address) {
-            Some(account) => match account.get(key) {
-                Some(val) => *val,
-                None => self.get_storage(address, key),
-            },
+        let origin = self
+            .store
+            .borrow()
+            .origin
+            .get(address)
+            .and_then(|account| account.get(key).cloned());
+        match origin {
+            Some(val) => val,
             None => self.get_storage(address, key),
         }
     }
@@ -740,12 +1706,35 @@ impl<B: DB + 'static> evm::DataProvider for DataProvider<B> {
         unimplemented!()
     }
 
+    fn get_transient_storage(&self, address: &Address, key: &H256) -> H256 {
+        self.store.borrow().get_transient_storage(address, key)
+    }
+
+    fn set_transient_storage(&mut self, address: &Address, key: H256, value: H256) {
+        self.store.borrow_mut().set_transient_storage(*address, key, value);
+    }
+
     fn selfdestruct(&mut self, address: &Address, refund_to: &Address) -> bool {
         if self.store.borrow_mut().selfdestruct.contains(address) {
             return false;
         }
-        //self.store.borrow_mut().used(refund_to.clone());
-        self.store.borrow_mut().selfdestruct.insert(*address);
+        // A contract created before the transaction is not deleted, only
+        // the balance is transferred. See: EIP 6780
+        if self.store.borrow().evm_cfg.eip6780 && !self.store.borrow().created.contains(address) {
+            self.store.borrow_mut().used(*refund_to);
+            self.store.borrow().access(refund_to);
+            if address != refund_to {
+                let b = self.get_balance(address);
+                self.state_provider
+                    .borrow_mut()
+                    .transfer_balance(address, refund_to, b)
+                    .unwrap();
+            }
+            return false;
+        }
+        self.store.borrow_mut().used(*refund_to);
+        self.store.borrow().access(refund_to);
+        self.store.borrow_mut().insert_selfdestruct(*address);
         let b = self.get_balance(address);
 
         if address != refund_to {
@@ -765,10 +1754,12 @@ impl<B: DB + 'static> evm::DataProvider for DataProvider<B> {
     }
 
     fn is_empty(&self, address: &Address) -> bool {
+        self.store.borrow().access(address);
         self.state_provider.borrow_mut().is_empty(address).unwrap_or(false)
     }
 
     fn exist(&self, address: &Address) -> bool {
+        self.store.borrow().access(address);
         self.state_provider.borrow_mut().exist(address).unwrap_or(false)
     }
 
@@ -776,6 +1767,13 @@ impl<B: DB + 'static> evm::DataProvider for DataProvider<B> {
         match opcode {
             evm::OpCode::CALL | evm::OpCode::CALLCODE | evm::OpCode::DELEGATECALL | evm::OpCode::STATICCALL => {
                 //self.store.borrow_mut().used(params.address);
+                self.store.borrow().access(&params.address);
+                self.store.borrow().access(&params.contract.code_address);
+                if let Some(authorizer) = &self.store.borrow().authorizer {
+                    if !authorizer.can_call(&params.sender, &params.contract.code_address, &params.input) {
+                        return Err(self.permission_denied(params.gas_limit));
+                    }
+                }
                 let r = call(
                     self.block_provider.clone(),
                     self.state_provider.clone(),
@@ -814,7 +1812,10 @@ impl<B: DB + 'static> evm::DataProvider for DataProvider<B> {
                     ),
                     _ => unimplemented!(),
                 }
-                .or(Err(evm::Error::CallError));
+                .map_err(|e| match e {
+                    err::Error::NoContractPermission => self.permission_denied(request.gas_limit),
+                    _ => evm::Error::CallError,
+                });
                 debug!("ext.create.result = {:?}", r);
                 r
             }
@@ -822,3 +1823,126 @@ impl<B: DB + 'static> evm::DataProvider for DataProvider<B> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_refund() {
+        let request = InterpreterParams {
+            gas_limit: 100_000,
+            ..Default::default()
+        };
+        let refund = |refund: u64, max_refund_quotient: u64| {
+            let store = Store {
+                refund,
+                evm_cfg: evm::InterpreterConf {
+                    max_refund_quotient,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            get_refund(Arc::new(RefCell::new(store)), &request, 60_000)
+        };
+        // 40000 gas is used, so the refund is capped at a half or a fifth of it.
+        assert_eq!(refund(15_000, 2), 15_000);
+        assert_eq!(refund(30_000, 2), 20_000);
+        assert_eq!(refund(30_000, 5), 8_000);
+        assert_eq!(refund(30_000, 0), 0);
+    }
+
+    #[test]
+    fn test_config_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Config>();
+    }
+
+    #[test]
+    fn test_clear() {
+        #[derive(Debug)]
+        struct HalfBurnedPolicy {
+            treasury: Address,
+        }
+
+        impl FeePolicy for HalfBurnedPolicy {
+            fn distribute(
+                &self,
+                evm_context: &evm::Context,
+                request: &InterpreterParams,
+                gas_used: u64,
+            ) -> Vec<(Address, U256)> {
+                let fee = request.gas_price * gas_used;
+                vec![(evm_context.coinbase, fee / 4), (self.treasury, fee / 4)]
+            }
+        }
+
+        let sender = Address::from_low_u64_be(1);
+        let coinbase = Address::from_low_u64_be(2);
+        let treasury = Address::from_low_u64_be(3);
+        let request = InterpreterParams {
+            sender,
+            gas_limit: 100_000,
+            gas_price: U256::from(2),
+            ..Default::default()
+        };
+        let balances = |fee_policy: &dyn FeePolicy| {
+            let db = Arc::new(crate::state::MemoryDB::new(false));
+            let state = Arc::new(RefCell::new(State::new(db).unwrap()));
+            let store = Arc::new(RefCell::new(Store {
+                evm_context: evm::Context {
+                    coinbase,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }));
+            // 40000 gas is used, and 10000 of it is refunded.
+            clear(state.clone(), store.clone(), fee_policy, &request, 60_000, 10_000).unwrap();
+            assert!(store.borrow().inused.contains(&coinbase));
+            let balance = |address: &Address| state.borrow_mut().balance(address).unwrap();
+            (balance(&sender), balance(&coinbase), balance(&treasury))
+        };
+
+        assert_eq!(
+            balances(&DefaultFeePolicy),
+            (U256::from(140_000), U256::from(60_000), U256::from(0))
+        );
+        assert_eq!(
+            balances(&HalfBurnedPolicy { treasury }),
+            (U256::from(140_000), U256::from(15_000), U256::from(15_000))
+        );
+    }
+
+    #[test]
+    fn test_apply_amend() {
+        let address = Address::from_low_u64_be(1);
+        let db = Arc::new(crate::state::MemoryDB::new(false));
+        let mut state = State::new(db).unwrap();
+        state.new_contract(&address, U256::from(100), U256::from(1), vec![]);
+        let state = Arc::new(RefCell::new(state));
+        let mut config = Config::default();
+        config.evm_cfg.max_create_code_size = 2;
+        let apply = |amend: Amend| apply_amend(state.clone(), &config, &amend);
+
+        // The balance is set rather than added, in both directions.
+        assert_eq!(
+            apply(Amend::Balance(address, U256::from(30))).unwrap(),
+            Amend::Balance(address, U256::from(100))
+        );
+        assert_eq!(
+            apply(Amend::Balance(address, U256::from(50))).unwrap(),
+            Amend::Balance(address, U256::from(30))
+        );
+        assert_eq!(state.borrow_mut().balance(&address).unwrap(), U256::from(50));
+
+        assert_eq!(
+            apply(Amend::Code(address, vec![0x60, 0x00])).unwrap(),
+            Amend::Code(address, vec![])
+        );
+        match apply(Amend::Code(address, vec![0; 3])) {
+            Err(err::Error::ExccedMaxCodeSize) => {}
+            _ => panic!("ExccedMaxCodeSize expected"),
+        }
+        assert_eq!(state.borrow_mut().code(&address).unwrap(), vec![0x60, 0x00]);
+    }
+}
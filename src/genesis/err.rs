@@ -0,0 +1,46 @@
+use std::error;
+use std::fmt;
+
+use ethereum_types::Address;
+
+use crate::state;
+
+#[derive(Debug)]
+pub enum Error {
+    Json(serde_json::Error),
+    State(state::Error),
+    InvalidAddress(String),
+    DuplicateAddress(Address),
+    InvalidField {
+        address: Address,
+        field: &'static str,
+        value: String,
+    },
+}
+
+impl error::Error for Error {}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Json(e) => write!(f, "genesis json: {}", e),
+            Error::State(e) => write!(f, "genesis {}", e),
+            Error::InvalidAddress(e) => write!(f, "genesis: invalid address {:?}", e),
+            Error::DuplicateAddress(e) => write!(f, "genesis: duplicate address {:?}", e),
+            Error::InvalidField { address, field, value } => {
+                write!(f, "genesis: invalid {} of {:?}: {:?}", field, address, value)
+            }
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::Json(error)
+    }
+}
+
+impl From<state::Error> for Error {
+    fn from(error: state::Error) -> Self {
+        Error::State(error)
+    }
+}
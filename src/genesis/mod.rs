@@ -0,0 +1,250 @@
+mod err;
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::sync::Arc;
+
+use cita_trie::DB;
+use ethereum_types::{Address, H256, U256};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::common::clean_0x;
+use crate::state::State;
+
+pub use err::Error;
+
+/// An account in the allocation, which is compatible with the `alloc` of
+/// geth's genesis. The balance and nonce are in hex with 0x prefix or in
+/// decimal, and the others are in hex. Storage keys and values shorter than
+/// 32 bytes are left padded with zeros.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenesisAccount {
+    pub balance: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub storage: BTreeMap<String, String>,
+    // CITA extras
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub abi: Option<String>,
+}
+
+/// Genesis loads the allocation of the initial state. Fields of geth's
+/// genesis other than `alloc` are ignored.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Genesis {
+    pub alloc: BTreeMap<String, GenesisAccount>,
+}
+
+// An account which is validated.
+struct Allocation {
+    balance: U256,
+    nonce: U256,
+    code: Vec<u8>,
+    abi: Vec<u8>,
+    storage: Vec<(H256, H256)>,
+}
+
+impl Genesis {
+    /// Load a genesis file.
+    pub fn load<R: Read>(reader: R) -> Result<Self, Error> {
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    /// Load the allocation only, which is the `alloc` of a genesis file.
+    pub fn load_alloc<R: Read>(reader: R) -> Result<Self, Error> {
+        Ok(Genesis {
+            alloc: serde_json::from_reader(reader)?,
+        })
+    }
+
+    /// Build the state of the allocation and commit it. All accounts are
+    /// validated before the state is changed.
+    pub fn build<B: DB>(&self, db: Arc<B>) -> Result<State<B>, Error> {
+        let allocations = self.validate()?;
+        let mut state = State::new(db)?;
+        for (address, allocation) in allocations {
+            state.new_contract(&address, allocation.balance, allocation.nonce, allocation.code);
+            if !allocation.abi.is_empty() {
+                state.set_abi(&address, allocation.abi)?;
+            }
+            for (key, value) in allocation.storage {
+                state.set_storage(&address, key, value)?;
+            }
+        }
+        state.commit()?;
+        Ok(state)
+    }
+
+    /// Build the state of the allocation and commit it. Return the state root.
+    pub fn commit<B: DB>(&self, db: Arc<B>) -> Result<H256, Error> {
+        Ok(self.build(db)?.root)
+    }
+
+    fn validate(&self) -> Result<BTreeMap<Address, Allocation>, Error> {
+        let mut allocations = BTreeMap::new();
+        for (address, account) in self.alloc.iter() {
+            let address = match hex::decode(clean_0x(address)) {
+                Ok(ref data) if data.len() == Address::len_bytes() => Address::from_slice(data),
+                _ => return Err(Error::InvalidAddress(address.clone())),
+            };
+            let invalid = |field: &'static str, value: &str| Error::InvalidField {
+                address,
+                field,
+                value: value.to_string(),
+            };
+            let balance = parse_quantity(&account.balance).ok_or_else(|| invalid("balance", &account.balance))?;
+            let nonce = match account.nonce {
+                Some(ref nonce) => parse_quantity(nonce).ok_or_else(|| invalid("nonce", nonce))?,
+                None => U256::zero(),
+            };
+            let code = match account.code {
+                Some(ref code) => hex::decode(clean_0x(code)).map_err(|_| invalid("code", code))?,
+                None => vec![],
+            };
+            let abi = match account.abi {
+                Some(ref abi) => hex::decode(clean_0x(abi)).map_err(|_| invalid("abi", abi))?,
+                None => vec![],
+            };
+            let mut storage = vec![];
+            for (key, value) in account.storage.iter() {
+                let k = parse_h256(key).ok_or_else(|| invalid("storage key", key))?;
+                let v = parse_h256(value).ok_or_else(|| invalid("storage value", value))?;
+                storage.push((k, v));
+            }
+            let allocation = Allocation {
+                balance,
+                nonce,
+                code,
+                abi,
+                storage,
+            };
+            if allocations.insert(address, allocation).is_some() {
+                return Err(Error::DuplicateAddress(address));
+            }
+        }
+        Ok(allocations)
+    }
+}
+
+fn parse_quantity(value: &str) -> Option<U256> {
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some("") => None,
+        Some(v) => U256::from_str_radix(v, 16).ok(),
+        None => U256::from_dec_str(value).ok(),
+    }
+}
+
+fn parse_h256(value: &str) -> Option<H256> {
+    let data = hex::decode(clean_0x(value)).ok()?;
+    if data.len() > 32 {
+        return None;
+    }
+    let mut h = H256::zero();
+    h.0[32 - data.len()..].copy_from_slice(&data);
+    Some(h)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{MemoryDB, StateObjectInfo};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_genesis() {
+        let a = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+        let b = Address::from_str("0x2000000000000000000000000000000000000000").unwrap();
+        let genesis = r#"{
+            "config": {"chainId": 1},
+            "gasLimit": "0x47b760",
+            "alloc": {
+                "0x1000000000000000000000000000000000000000": {
+                    "balance": "0x10",
+                    "nonce": "1",
+                    "code": "0x6000",
+                    "storage": {
+                        "0x01": "0x0000000000000000000000000000000000000000000000000000000000000102"
+                    },
+                    "abi": "0x616269"
+                },
+                "2000000000000000000000000000000000000000": {"balance": "1000000000000000000000"}
+            }
+        }"#;
+        let genesis = Genesis::load(genesis.as_bytes()).unwrap();
+        let db = Arc::new(MemoryDB::new(false));
+        let mut state = genesis.build(db).unwrap();
+        assert_eq!(state.balance(&a).unwrap(), U256::from(16));
+        assert_eq!(state.nonce(&a).unwrap(), U256::from(1));
+        assert_eq!(state.code(&a).unwrap(), vec![0x60, 0x00]);
+        assert_eq!(state.abi(&a).unwrap(), b"abi".to_vec());
+        assert_eq!(
+            state.get_storage(&a, &H256::from_low_u64_be(1)).unwrap(),
+            H256::from_low_u64_be(0x102)
+        );
+        assert_eq!(
+            state.balance(&b).unwrap(),
+            U256::from_dec_str("1000000000000000000000").unwrap()
+        );
+
+        // The root is the same as the state built by hand.
+        let mut expected = State::new(Arc::new(MemoryDB::new(false))).unwrap();
+        expected.new_contract(&a, U256::from(16), U256::from(1), vec![0x60, 0x00]);
+        expected.set_abi(&a, b"abi".to_vec()).unwrap();
+        expected
+            .set_storage(&a, H256::from_low_u64_be(1), H256::from_low_u64_be(0x102))
+            .unwrap();
+        expected.new_contract(
+            &b,
+            U256::from_dec_str("1000000000000000000000").unwrap(),
+            U256::from(0),
+            vec![],
+        );
+        expected.commit().unwrap();
+        let alloc = serde_json::to_string(&genesis.alloc).unwrap();
+        let genesis = Genesis::load_alloc(alloc.as_bytes()).unwrap();
+        let root = genesis.commit(Arc::new(MemoryDB::new(false))).unwrap();
+        assert_eq!(root, expected.root);
+
+        // Quantities in hex may be prefixed with 0X as well.
+        let genesis = Genesis::load_alloc(
+            r#"{"0x1000000000000000000000000000000000000000": {"balance": "0X10", "nonce": "0X2"}}"#.as_bytes(),
+        )
+        .unwrap();
+        let mut state = genesis.build(Arc::new(MemoryDB::new(false))).unwrap();
+        let address = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+        assert_eq!(state.balance(&address).unwrap(), U256::from(16));
+        assert_eq!(state.nonce(&address).unwrap(), U256::from(2));
+
+        let invalid = |alloc: &str| {
+            let genesis = Genesis::load_alloc(alloc.as_bytes()).unwrap();
+            let err = genesis.build(Arc::new(MemoryDB::new(false))).err().unwrap();
+            format!("{}", err)
+        };
+        assert_eq!(
+            invalid(r#"{"0x10": {"balance": "1"}}"#),
+            "genesis: invalid address \"0x10\""
+        );
+        assert_eq!(
+            invalid(r#"{"0x1000000000000000000000000000000000000000": {"balance": "0xg"}}"#),
+            "genesis: invalid balance of 0x1000000000000000000000000000000000000000: \"0xg\""
+        );
+        assert_eq!(
+            invalid(r#"{"0x1000000000000000000000000000000000000000": {"balance": "1", "code": "0x600"}}"#),
+            "genesis: invalid code of 0x1000000000000000000000000000000000000000: \"0x600\""
+        );
+        assert!(invalid(
+            r#"{"0x1000000000000000000000000000000000000000": {"balance": "1", "storage": {"0x01": "0x0000000000000000000000000000000000000000000000000000000000000000ff"}}}"#
+        )
+        .starts_with("genesis: invalid storage value"));
+        assert_eq!(
+            invalid(
+                r#"{"0x1000000000000000000000000000000000000000": {"balance": "1"}, "1000000000000000000000000000000000000000": {"balance": "2"}}"#
+            ),
+            "genesis: duplicate address 0x1000000000000000000000000000000000000000"
+        );
+        assert!(Genesis::load_alloc(r#"{"0x10": {}}"#.as_bytes()).is_err());
+    }
+}
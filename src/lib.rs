@@ -2,6 +2,7 @@ pub mod common;
 mod err;
 pub mod evm;
 mod executive;
+pub mod genesis;
 pub mod json_tests;
 #[allow(dead_code)]
 pub mod native;
@@ -10,6 +11,8 @@ pub mod state;
 pub use common::hash::summary;
 pub use err::Error;
 pub use executive::{
-    create_address_from_address_and_nonce, exec, exec_static, BlockDataProvider, BlockDataProviderMock, Config,
-    CreateKind, DataProvider, Executive, Store, Transaction,
+    create_address_from_address_and_nonce, exec, exec_amend, exec_create_access_list, exec_dry_run_create, exec_static,
+    exec_with_state_diff, get_blob_base_fee, AccessListItem, Amend, AmendReceipt, Authorizer, BlockDataProvider,
+    BlockDataProviderMock, Config, CreateAccessList, CreateKind, DataProvider, DefaultAuthorizer, DefaultFeePolicy,
+    DryRunCreate, ExecStateDiff, Executive, FeePolicy, GasSchedule, NonceMode, Store, Transaction,
 };
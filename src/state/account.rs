@@ -277,13 +277,24 @@ impl StateObject {
 
     /// Flush data in storage cache to database.
     pub fn commit_storage<B: DB>(&mut self, db: Arc<B>) -> Result<(), Error> {
+        self.storage_root = self.compute_storage_root(db)?;
+        self.storage_changes.clear();
+        Ok(())
+    }
+
+    /// Write data in storage cache to the storage trie in database and return
+    /// the new root. The storage cache is kept.
+    pub fn compute_storage_root<B: DB>(&self, db: Arc<B>) -> Result<H256, Error> {
+        if self.storage_changes.is_empty() {
+            return Ok(self.storage_root);
+        }
         let mut trie = if self.storage_root == common::hash::RLP_NULL {
             PatriciaTrie::new(db, Arc::new(hash::get_hasher()))
         } else {
             PatriciaTrie::from(db, Arc::new(hash::get_hasher()), &self.storage_root.0)?
         };
 
-        for (k, v) in self.storage_changes.drain() {
+        for (k, v) in self.storage_changes.iter() {
             if v.is_zero() {
                 trie.remove(k.as_bytes())?;
             } else {
@@ -291,8 +302,7 @@ impl StateObject {
             }
         }
 
-        self.storage_root = H256::from_slice(trie.root()?.as_slice());
-        Ok(())
+        Ok(H256::from_slice(trie.root()?.as_slice()))
     }
 
     /// Flush code to database if necessary.
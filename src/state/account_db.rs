@@ -6,7 +6,7 @@ use std::sync::Arc;
 
 static NULL_RLP_STATIC: [u8; 1] = [0x80; 1];
 
-fn combine_key(addr_hash: &[u8], key: &[u8]) -> Vec<u8> {
+pub(crate) fn combine_key(addr_hash: &[u8], key: &[u8]) -> Vec<u8> {
     let mut dst = key.to_owned().to_vec();
     {
         for (k, a) in dst[12..].iter_mut().zip(&addr_hash[12..]) {
@@ -0,0 +1,89 @@
+use std::collections::BTreeMap;
+use std::mem;
+use std::sync::{Arc, RwLock};
+
+use cita_trie::DB;
+use ethereum_types::H256;
+
+use crate::state::err::Error;
+
+/// Writes of a commit, which are collected by `State::commit_batch` and
+/// applied to the database by the caller.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WriteBatch {
+    pub root: H256, // The state root after the commit
+    // Key and value of each write, the value is none for removals.
+    pub changes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl WriteBatch {
+    /// Write the changes into the database and flush it.
+    pub fn apply<B: DB>(&self, db: &B) -> Result<(), Error> {
+        for (key, value) in self.changes.iter() {
+            match value {
+                Some(value) => db.insert(key.clone(), value.clone()),
+                None => db.remove(key),
+            }
+            .map_err(|e| Error::DB(format!("{}", e)))?;
+        }
+        db.flush().map_err(|e| Error::DB(format!("{}", e)))
+    }
+}
+
+/// OverlayDB keeps writes in memory on top of a database, which is only read.
+#[derive(Debug)]
+pub struct OverlayDB<B> {
+    db: Arc<B>,
+    changes: RwLock<BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
+}
+
+impl<B: DB> OverlayDB<B> {
+    pub fn new(db: Arc<B>) -> Self {
+        OverlayDB {
+            db,
+            changes: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Take the writes kept in memory.
+    pub fn take_batch(&self, root: H256) -> WriteBatch {
+        WriteBatch {
+            root,
+            changes: mem::take(&mut *self.changes.write().unwrap()),
+        }
+    }
+}
+
+impl<B: DB> DB for OverlayDB<B> {
+    type Error = Error;
+
+    // A removed key is still read from the database, since whether the
+    // removal takes effect is up to the database.
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        if let Some(Some(value)) = self.changes.read().unwrap().get(key) {
+            return Ok(Some(value.clone()));
+        }
+        self.db.get(key).map_err(|e| Error::DB(format!("{}", e)))
+    }
+
+    fn contains(&self, key: &[u8]) -> Result<bool, Error> {
+        if let Some(Some(_)) = self.changes.read().unwrap().get(key) {
+            return Ok(true);
+        }
+        self.db.contains(key).map_err(|e| Error::DB(format!("{}", e)))
+    }
+
+    fn insert(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), Error> {
+        self.changes.write().unwrap().insert(key, Some(value));
+        Ok(())
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<(), Error> {
+        self.changes.write().unwrap().insert(key.to_vec(), None);
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
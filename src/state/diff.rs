@@ -0,0 +1,146 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use cita_trie::{PatriciaTrie, Trie, DB};
+use ethereum_types::{Address, H256, U256};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::common::hash;
+use crate::state::account::StateObject;
+use crate::state::account_db::AccountDB;
+use crate::state::err::Error;
+use crate::state::state::{Checkpoint, State};
+
+/// Values of an account in a state diff. Fields which are not included are
+/// left as none.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountState {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balance: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<U256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code_hash: Option<H256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub abi_hash: Option<H256>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub storage: BTreeMap<H256, H256>,
+}
+
+/// Changes of accounts, which is laid out like the diff mode of geth's
+/// prestateTracer:
+///   1) pre holds all fields of changed accounts before the change, and the
+///      changed storage slots. Created accounts are not included.
+///   2) post holds the changed fields and storage slots after the change.
+///      Deleted accounts are not included.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateDiff {
+    pub pre: BTreeMap<Address, AccountState>,
+    pub post: BTreeMap<Address, AccountState>,
+}
+
+impl StateDiff {
+    /// Accounts which did not exist before.
+    pub fn created(&self) -> BTreeSet<Address> {
+        self.post
+            .keys()
+            .filter(|a| !self.pre.contains_key(a))
+            .cloned()
+            .collect()
+    }
+
+    /// Accounts which do not exist anymore.
+    pub fn deleted(&self) -> BTreeSet<Address> {
+        self.pre
+            .keys()
+            .filter(|a| !self.post.contains_key(a))
+            .cloned()
+            .collect()
+    }
+}
+
+impl<B: DB> State<B> {
+    /// Returns the changes made since the checkpoint, which must not be
+    /// discarded yet. Only accounts recorded in the journal since then are
+    /// compared, so the cost does not grow with the cache.
+    pub fn diff_since(&self, checkpoint: Checkpoint) -> Result<StateDiff, Error> {
+        let mut diff = StateDiff::default();
+        for (address, entry) in self.entries_at(checkpoint)? {
+            let pre = match entry {
+                Some(entry) => entry.state_object,
+                None => self.get_committed_state_object(&address)?,
+            };
+            let post = self
+                .cache
+                .borrow()
+                .get(&address)
+                .and_then(|entry| entry.state_object.as_ref().map(StateObject::clone_dirty));
+            let accdb = Arc::new(AccountDB::new(address, self.db.clone()));
+            let mut keys = BTreeSet::new();
+            for state_object in pre.iter().chain(post.iter()) {
+                keys.extend(state_object.storage_changes.keys().cloned());
+            }
+            let mut pre_storage = BTreeMap::new();
+            let mut post_storage = BTreeMap::new();
+            for key in keys {
+                let pre_value = storage_of(pre.as_ref(), accdb.clone(), &key)?;
+                let post_value = storage_of(post.as_ref(), accdb.clone(), &key)?;
+                if pre_value != post_value {
+                    pre_storage.insert(key, pre_value);
+                    post_storage.insert(key, post_value);
+                }
+            }
+            match (pre, post) {
+                (None, None) => {}
+                (None, Some(post)) => {
+                    post_storage.retain(|_, v| !v.is_zero());
+                    diff.post.insert(address, account_state(&post, post_storage));
+                }
+                (Some(pre), None) => {
+                    diff.pre.insert(address, account_state(&pre, pre_storage));
+                }
+                (Some(pre), Some(post)) => {
+                    let changed = AccountState {
+                        balance: Some(post.balance).filter(|v| *v != pre.balance),
+                        nonce: Some(post.nonce).filter(|v| *v != pre.nonce),
+                        code_hash: Some(post.code_hash).filter(|v| *v != pre.code_hash),
+                        abi_hash: Some(post.abi_hash).filter(|v| *v != pre.abi_hash),
+                        storage: post_storage,
+                    };
+                    if changed != AccountState::default() {
+                        diff.pre.insert(address, account_state(&pre, pre_storage));
+                        diff.post.insert(address, changed);
+                    }
+                }
+            }
+        }
+        Ok(diff)
+    }
+
+    /// Get the state object in the database, ignoring the cache.
+    fn get_committed_state_object(&self, address: &Address) -> Result<Option<StateObject>, Error> {
+        let trie = PatriciaTrie::from(Arc::clone(&self.db), Arc::new(hash::get_hasher()), &self.root.0)?;
+        match trie.get(&address[..])? {
+            Some(rlp) => Ok(Some(StateObject::from_rlp(&rlp)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+fn storage_of<B: DB>(state_object: Option<&StateObject>, accdb: Arc<AccountDB<B>>, key: &H256) -> Result<H256, Error> {
+    match state_object {
+        Some(state_object) => Ok(state_object.get_storage(accdb, key)?.unwrap_or_else(H256::zero)),
+        None => Ok(H256::zero()),
+    }
+}
+
+fn account_state(state_object: &StateObject, storage: BTreeMap<H256, H256>) -> AccountState {
+    AccountState {
+        balance: Some(state_object.balance),
+        nonce: Some(state_object.nonce),
+        code_hash: Some(state_object.code_hash),
+        abi_hash: Some(state_object.abi_hash),
+        storage,
+    }
+}
@@ -0,0 +1,419 @@
+use std::collections::{btree_map, BTreeMap};
+use std::iter::Peekable;
+use std::sync::Arc;
+
+use cita_trie::DB;
+use ethereum_types::{Address, BigEndianHash, H256, U256};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::common::hash;
+use crate::state::account::StateObject;
+use crate::state::account_db::AccountDB;
+use crate::state::batch::OverlayDB;
+use crate::state::err::Error;
+use crate::state::pruning::nibbles_to_bytes;
+use crate::state::state::State;
+
+/// Accounts of a state, which is laid out like the output of geth's `dump`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dump {
+    pub root: H256,
+    pub accounts: BTreeMap<Address, DumpAccount>,
+}
+
+/// An account in a dump. The code, abi and storage are encoded as hex, and
+/// storage values are trimmed of leading zeros like geth does. The nonce is
+/// a number as in geth, so a state with a nonce beyond u64 can not be dumped.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DumpAccount {
+    pub balance: String, // In decimal
+    pub nonce: u64,
+    pub root: H256,
+    pub code_hash: H256,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub code: String,
+    pub abi_hash: H256,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub abi: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub storage: BTreeMap<H256, String>,
+    pub address: Address,
+}
+
+impl<B: DB> State<B> {
+    /// Returns all accounts of the state ordered by address, including the
+    /// changes in the cache. Accounts are read from the trie as the iterator
+    /// advances, and their code and abi are not read, see
+    /// `StateObject::read_code`.
+    pub fn iter_accounts(&self) -> AccountIter<B> {
+        let cache: BTreeMap<_, _> = self
+            .cache
+            .borrow()
+            .iter()
+            .map(|(address, entry)| (*address, entry.state_object.clone()))
+            .collect();
+        AccountIter {
+            trie: TrieWalker::new(self.db.clone(), &self.root),
+            next: None,
+            cache: cache.into_iter().peekable(),
+        }
+    }
+
+    /// Returns the non-zero storage of an account ordered by key, including
+    /// the changes in the cache. Like `iter_accounts`, the storage is read
+    /// from the trie as the iterator advances.
+    pub fn iter_storage(&self, address: &Address) -> Result<StorageIter<AccountDB<B>>, Error> {
+        let accdb = Arc::new(AccountDB::new(*address, self.db.clone()));
+        // An account killed in the cache has no storage, while
+        // get_state_object would read it from the trie again.
+        self.call_with_cached(address, |a| match a {
+            Some(state_object) => StorageIter::new(state_object, accdb.clone()),
+            None => StorageIter::new(&StateObject::new(U256::zero(), U256::zero()), accdb.clone()),
+        })
+    }
+
+    /// Dump all accounts and their storage. Roots are computed as if the
+    /// cache were committed, see `compute_root`.
+    pub fn dump(&self) -> Result<Dump, Error> {
+        let overlay = Arc::new(OverlayDB::new(self.db.clone()));
+        let mut accounts = BTreeMap::new();
+        for account in self.iter_accounts() {
+            let (address, mut state_object) = account?;
+            let accdb = Arc::new(AccountDB::new(address, overlay.clone()));
+            // Code and abi changed in the cache are loaded already.
+            if state_object.code.is_empty() {
+                state_object.read_code(accdb.clone())?;
+            }
+            if state_object.abi.is_empty() {
+                state_object.read_abi(accdb.clone())?;
+            }
+            let mut storage = BTreeMap::new();
+            for item in StorageIter::new(&state_object, accdb.clone()) {
+                let (k, v) = item?;
+                let v = v.as_bytes();
+                let start = v.iter().position(|b| *b != 0).unwrap_or(v.len());
+                storage.insert(k, hex::encode(&v[start..]));
+            }
+            if state_object.nonce > U256::from(u64::MAX) {
+                return Err(Error::NonceOverflow(address));
+            }
+            let account = DumpAccount {
+                balance: state_object.balance.to_string(),
+                nonce: state_object.nonce.low_u64(),
+                root: state_object.compute_storage_root(accdb)?,
+                code_hash: state_object.code_hash,
+                code: encode_hex(&state_object.code),
+                abi_hash: state_object.abi_hash,
+                abi: encode_hex(&state_object.abi),
+                storage,
+                address,
+            };
+            accounts.insert(address, account);
+        }
+        Ok(Dump {
+            root: self.compute_root()?,
+            accounts,
+        })
+    }
+}
+
+/// Iterator over accounts of a state, see `State::iter_accounts`. It walks
+/// the world trie in the order of addresses, and merges the cache into it.
+pub struct AccountIter<B> {
+    trie: TrieWalker<B>,
+    next: Option<(Address, StateObject)>, // The next account in the trie
+    cache: Peekable<btree_map::IntoIter<Address, Option<StateObject>>>,
+}
+
+type Leaf = (Vec<u8>, Vec<u8>);
+
+// Walks the leaves of a trie in the order of keys. Nodes are read from the
+// db as it advances.
+struct TrieWalker<D> {
+    db: Arc<D>,
+    // Encoded nodes to visit, or hashes of them, with the nibbles of the
+    // paths to them. The last one is visited first.
+    nodes: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl<D: DB> TrieWalker<D> {
+    fn new(db: Arc<D>, root: &H256) -> Self {
+        let mut nodes = vec![];
+        if *root != hash::RLP_NULL {
+            nodes.push((rlp::encode(root).to_vec(), vec![]));
+        }
+        TrieWalker { db, nodes }
+    }
+
+    // Returns the key and value of the next leaf whose key is key_len bytes.
+    fn next_leaf(&mut self, key_len: usize) -> Result<Option<Leaf>, Error> {
+        while let Some((data, path)) = self.nodes.pop() {
+            let mut node = rlp::Rlp::new(&data);
+            // A node is referred by its hash if it is not embedded.
+            let fetched;
+            if !node.is_list() {
+                let hash = node.data()?;
+                if hash.len() != 32 {
+                    continue;
+                }
+                fetched = self
+                    .db
+                    .get(hash)
+                    .map_err(|e| Error::DB(format!("{}", e)))?
+                    .ok_or(Error::NotFound)?;
+                node = rlp::Rlp::new(&fetched);
+            }
+            match node.item_count()? {
+                // Branch node, children are pushed reversely so the first one
+                // is visited first. The value is never used since keys are in
+                // same length.
+                17 => {
+                    for i in (0..16).rev() {
+                        let mut p = path.clone();
+                        p.push(i as u8);
+                        self.nodes.push((node.at(i)?.as_raw().to_vec(), p));
+                    }
+                }
+                // Leaf or extension node, whose path is hex-prefix encoded.
+                2 => {
+                    let encoded: Vec<u8> = node.val_at(0)?;
+                    let mut p = path;
+                    let is_leaf = match encoded.first() {
+                        Some(first) => {
+                            if first & 0x10 != 0 {
+                                p.push(first & 0x0f);
+                            }
+                            first & 0x20 != 0
+                        }
+                        None => continue,
+                    };
+                    for b in &encoded[1..] {
+                        p.push(b >> 4);
+                        p.push(b & 0x0f);
+                    }
+                    if !is_leaf {
+                        self.nodes.push((node.at(1)?.as_raw().to_vec(), p));
+                    } else if p.len() == key_len * 2 {
+                        return Ok(Some((nibbles_to_bytes(&p), node.val_at(1)?)));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<B: DB> Iterator for AccountIter<B> {
+    type Item = Result<(Address, StateObject), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.next.is_none() {
+                let next = self.trie.next_leaf(Address::len_bytes()).and_then(|leaf| match leaf {
+                    Some((key, value)) => Ok(Some((Address::from_slice(&key), StateObject::from_rlp(&value)?))),
+                    None => Ok(None),
+                });
+                match next {
+                    Ok(next) => self.next = next,
+                    Err(e) => {
+                        self.trie.nodes.clear();
+                        return Some(Err(e));
+                    }
+                }
+            }
+            // The cache overrides the trie.
+            let from_cache = match (&self.next, self.cache.peek()) {
+                (Some((address, _)), Some((cached, _))) => cached <= address,
+                (None, Some(_)) => true,
+                (_, None) => false,
+            };
+            if !from_cache {
+                return self.next.take().map(Ok);
+            }
+            let (address, state_object) = self.cache.next()?;
+            if let Some((next, _)) = self.next {
+                if next == address {
+                    self.next = None;
+                }
+            }
+            if let Some(state_object) = state_object {
+                return Some(Ok((address, state_object)));
+            }
+        }
+    }
+}
+
+/// Iterator over the non-zero storage of an account, see
+/// `State::iter_storage`. It walks the storage trie in the order of keys, and
+/// merges the changes in the cache into it.
+pub struct StorageIter<D> {
+    trie: TrieWalker<D>,
+    next: Option<(H256, H256)>, // The next slot in the trie
+    changes: Peekable<btree_map::IntoIter<H256, H256>>,
+}
+
+impl<D: DB> StorageIter<D> {
+    fn new(state_object: &StateObject, accdb: Arc<D>) -> Self {
+        let changes: BTreeMap<_, _> = state_object.storage_changes.iter().map(|(k, v)| (*k, *v)).collect();
+        StorageIter {
+            trie: TrieWalker::new(accdb, &state_object.storage_root),
+            next: None,
+            changes: changes.into_iter().peekable(),
+        }
+    }
+}
+
+impl<D: DB> Iterator for StorageIter<D> {
+    type Item = Result<(H256, H256), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.next.is_none() {
+                let next = self.trie.next_leaf(32).and_then(|leaf| match leaf {
+                    Some((key, value)) => {
+                        let value: U256 = rlp::decode(&value)?;
+                        Ok(Some((H256::from_slice(&key), H256::from_uint(&value))))
+                    }
+                    None => Ok(None),
+                });
+                match next {
+                    Ok(next) => self.next = next,
+                    Err(e) => {
+                        self.trie.nodes.clear();
+                        return Some(Err(e));
+                    }
+                }
+            }
+            // The changes override the trie, and a zero value is a deletion.
+            let from_changes = match (&self.next, self.changes.peek()) {
+                (Some((key, _)), Some((changed, _))) => changed <= key,
+                (None, Some(_)) => true,
+                (_, None) => false,
+            };
+            if !from_changes {
+                return self.next.take().map(Ok);
+            }
+            let (key, value) = self.changes.next()?;
+            if let Some((next, _)) = self.next {
+                if next == key {
+                    self.next = None;
+                }
+            }
+            if !value.is_zero() {
+                return Some(Ok((key, value)));
+            }
+        }
+    }
+}
+
+fn encode_hex(data: &[u8]) -> String {
+    if data.is_empty() {
+        return String::new();
+    }
+    String::from("0x") + &hex::encode(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cita_trie::MemoryDB;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_dump() {
+        let a = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+        let b = Address::from_str("0x2000000000000000000000000000000000000000").unwrap();
+        let c = Address::from_str("0x3000000000000000000000000000000000000000").unwrap();
+        let d = Address::from_str("0x4000000000000000000000000000000000000000").unwrap();
+
+        let db = Arc::new(MemoryDB::new(false));
+        let mut state = State::new(db).unwrap();
+        state.new_contract(&a, U256::from(10), U256::from(1), vec![0x60, 0x00]);
+        state.set_abi(&a, b"abi".to_vec()).unwrap();
+        for i in 1..3 {
+            state
+                .set_storage(&a, H256::from_low_u64_be(i), H256::from_low_u64_be(0x100 + i))
+                .unwrap();
+        }
+        state.new_contract(&b, U256::from(20), U256::from(0), vec![]);
+        state.new_contract(&c, U256::from(30), U256::from(0), vec![]);
+        state.commit().unwrap();
+
+        // Changes in the cache are merged.
+        state.set_storage(&a, H256::from_low_u64_be(1), H256::zero()).unwrap();
+        state
+            .set_storage(&a, H256::from_low_u64_be(3), H256::from_low_u64_be(0x103))
+            .unwrap();
+        state.add_balance(&b, U256::from(1)).unwrap();
+        state.kill_contract(&c);
+        state.new_contract(&d, U256::from(40), U256::from(u64::MAX), vec![]);
+
+        let accounts: Vec<_> = state.iter_accounts().collect::<Result<_, _>>().unwrap();
+        assert_eq!(accounts.iter().map(|(k, _)| *k).collect::<Vec<_>>(), vec![a, b, d]);
+        assert_eq!(accounts[0].1.nonce, U256::from(1));
+        assert_eq!(accounts[1].1.balance, U256::from(21));
+        let storage: Vec<_> = state.iter_storage(&a).unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(
+            storage,
+            vec![
+                (H256::from_low_u64_be(2), H256::from_low_u64_be(0x102)),
+                (H256::from_low_u64_be(3), H256::from_low_u64_be(0x103)),
+            ]
+        );
+        assert_eq!(state.iter_storage(&c).unwrap().count(), 0);
+
+        let dump = state.dump().unwrap();
+        assert_eq!(dump.root, state.compute_root().unwrap());
+        let json = serde_json::to_value(&dump).unwrap();
+        let account = &json["accounts"]["0x1000000000000000000000000000000000000000"];
+        assert_eq!(account["balance"], "10");
+        assert_eq!(account["nonce"], 1);
+        assert_eq!(account["code"], "0x6000");
+        assert_eq!(account["abi"], "0x616269");
+        assert_eq!(
+            account["storage"]["0x0000000000000000000000000000000000000000000000000000000000000003"],
+            "0103"
+        );
+        let account = &json["accounts"]["0x4000000000000000000000000000000000000000"];
+        assert_eq!(account["nonce"], u64::MAX);
+        assert!(account.get("storage").is_none());
+
+        // The dump is the same after the cache is committed.
+        state.commit().unwrap();
+        assert_eq!(state.dump().unwrap(), dump);
+        assert_eq!(dump.root, state.root);
+
+        // Nonces beyond u64 can not be dumped.
+        state.inc_nonce(&d).unwrap();
+        assert_eq!(state.dump(), Err(Error::NonceOverflow(d)));
+        state.kill_contract(&d);
+        state.commit().unwrap();
+
+        // Accounts are walked in order through branch and extension nodes.
+        let mut addresses: Vec<_> = (0..100u64)
+            .map(|i| Address::from_slice(&hash::summary(&i.to_be_bytes())[12..]))
+            .collect();
+        for address in addresses.iter() {
+            state.new_contract(address, U256::from(1), U256::from(0), vec![]);
+        }
+        state.commit().unwrap();
+        addresses.extend_from_slice(&[a, b]);
+        addresses.sort();
+        let accounts: Vec<_> = state.iter_accounts().map(|r| r.unwrap().0).collect();
+        assert_eq!(accounts, addresses);
+
+        // So is storage.
+        let mut keys: Vec<_> = (0..100u64)
+            .map(|i| H256::from_slice(&hash::summary(&i.to_be_bytes())))
+            .collect();
+        for key in keys.iter() {
+            state.set_storage(&b, *key, H256::from_low_u64_be(1)).unwrap();
+        }
+        state.commit().unwrap();
+        keys.sort();
+        let storage: Vec<_> = state.iter_storage(&b).unwrap().map(|r| r.unwrap().0).collect();
+        assert_eq!(storage, keys);
+    }
+}
@@ -1,3 +1,5 @@
+use ethereum_types::Address;
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Error {
     Trie(String),
@@ -5,6 +7,8 @@ pub enum Error {
     DB(String),
     NotFound,
     BalanceError,
+    CheckpointOutOfOrder,
+    NonceOverflow(Address), // The nonce of the account does not fit in a dump
 }
 
 impl std::error::Error for Error {}
@@ -16,6 +20,8 @@ impl std::fmt::Display for Error {
             Error::DB(e) => write!(f, "state db: {}", e),
             Error::NotFound => write!(f, "state: not found"),
             Error::BalanceError => write!(f, "state: balance error"),
+            Error::CheckpointOutOfOrder => write!(f, "state: checkpoint out of order"),
+            Error::NonceOverflow(address) => write!(f, "state: nonce of {:?} overflows u64", address),
         }
     }
 }
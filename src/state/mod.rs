@@ -1,13 +1,23 @@
 mod account;
 mod account_db;
+mod batch;
+mod diff;
+mod dump;
 mod err;
 mod object_entry;
+mod pruning;
+mod reorg;
 #[allow(clippy::module_inception)]
 mod state;
 
 pub use account::{Account, CodeState, StateObject};
 pub use account_db::AccountDB;
+pub use batch::WriteBatch;
 pub use cita_trie::MemoryDB;
+pub use diff::{AccountState, StateDiff};
+pub use dump::{AccountIter, Dump, DumpAccount, StorageIter};
 pub use err::Error;
 pub use object_entry::{ObjectStatus, StateObjectEntry};
-pub use state::{State, StateObjectInfo};
+pub use pruning::Pruning;
+pub use reorg::Reorg;
+pub use state::{Checkpoint, State, StateObjectInfo};
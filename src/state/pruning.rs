@@ -0,0 +1,259 @@
+use cita_trie::DB;
+use ethereum_types::{Address, H256};
+
+use crate::common::hash::{summary, NIL_DATA, RLP_NULL};
+use crate::state::account::Account;
+use crate::state::account_db::combine_key;
+use crate::state::batch::WriteBatch;
+use crate::state::err::Error;
+
+const PREFIX_LEN: usize = 12;
+// Roots kept by pruning, from the oldest to the latest.
+const PRUNING_ROOTS_KEY: [u8; PREFIX_LEN] = [b'p', b'r', b'u', b'n', b'e', 0, 0, 0, 0, 0, 0, 0];
+// Prefix of the reference count of a node.
+const REFS_PREFIX: [u8; 4] = [b'r', b'e', b'f', b's'];
+
+/// The trie a node belongs to, which decides how the node is keyed in the
+/// database and what it refers to.
+#[derive(Clone, Copy)]
+enum NodeKind {
+    World,
+    Storage(H256), // The hash of the address, see AccountDB
+    Blob,          // Code or abi
+}
+
+// Key, trie and nibbles of the path of a node referred by another node.
+type Child = (Vec<u8>, NodeKind, Vec<u8>);
+
+/// Pruning reference-counts the nodes of the world trie and storage tries,
+/// and deletes nodes which are not reachable from the kept roots.
+///
+/// A node is referred by its parents, and a root is referred once for each
+/// commit. When a node is referred for the first time, the nodes it refers
+/// to are counted as well, and vice versa for the last time. Accounts refer
+/// to their storage roots, code and abi. Identical accounts at different
+/// addresses share a world node but not their storage, so world nodes are
+/// counted per path.
+///
+/// Nodes written before pruning is enabled are counted when they are first
+/// reachable from a kept root, so nodes only reachable from older roots are
+/// never deleted.
+pub struct Pruning<'a, B> {
+    db: &'a B,
+}
+
+impl<'a, B: DB> Pruning<'a, B> {
+    pub fn new(db: &'a B) -> Self {
+        Pruning { db }
+    }
+
+    /// Roots kept by pruning, from the oldest to the latest.
+    pub fn roots(&self) -> Result<Vec<H256>, Error> {
+        match self.get(&PRUNING_ROOTS_KEY)? {
+            Some(data) => Ok(rlp::decode_list(&data)),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Write the batch and keep its root, then release roots older than the
+    /// last `history` ones. The latest root is always kept. Removals in the
+    /// batch are ignored, nodes are removed only by pruning.
+    pub fn commit(&self, batch: &WriteBatch, history: usize) -> Result<(), Error> {
+        for (key, value) in batch.changes.iter() {
+            if let Some(value) = value {
+                self.insert(key.clone(), value.clone())?;
+            }
+        }
+        let mut roots = self.roots()?;
+        self.refer(batch.root.0.to_vec(), NodeKind::World, vec![])?;
+        roots.push(batch.root);
+        let released = roots.len().saturating_sub(std::cmp::max(history, 1));
+        for root in roots.drain(..released) {
+            self.release(root.0.to_vec(), NodeKind::World, vec![])?;
+        }
+        self.insert(PRUNING_ROOTS_KEY.to_vec(), rlp::encode_list(&roots).to_vec())?;
+        self.db.flush().map_err(|e| Error::DB(format!("{}", e)))
+    }
+
+    fn refer(&self, key: Vec<u8>, kind: NodeKind, path: Vec<u8>) -> Result<(), Error> {
+        let counter = counter_key(&key, kind, &path);
+        let refs = self.refs(&counter)?;
+        self.set_refs(&counter, refs + 1)?;
+        if refs == 0 {
+            if let NodeKind::World = kind {
+                let paths = self.refs(&refs_key(&key))?;
+                self.set_refs(&refs_key(&key), paths + 1)?;
+            }
+            for (key, kind, path) in self.children(&key, kind, path)? {
+                self.refer(key, kind, path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn release(&self, key: Vec<u8>, kind: NodeKind, path: Vec<u8>) -> Result<(), Error> {
+        let counter = counter_key(&key, kind, &path);
+        let refs = self.refs(&counter)?;
+        if refs > 1 {
+            return self.set_refs(&counter, refs - 1);
+        }
+        let children = self.children(&key, kind, path)?;
+        self.set_refs(&counter, 0)?;
+        let live = match kind {
+            NodeKind::World => {
+                let paths = self.refs(&refs_key(&key))?.saturating_sub(1);
+                self.set_refs(&refs_key(&key), paths)?;
+                paths > 0
+            }
+            _ => false,
+        };
+        if !live {
+            self.remove(&key)?;
+        }
+        for (key, kind, path) in children {
+            self.release(key, kind, path)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the nodes referred by the node, with the nibbles of the path
+    /// to them in the trie.
+    fn children(&self, key: &[u8], kind: NodeKind, path: Vec<u8>) -> Result<Vec<Child>, Error> {
+        if let NodeKind::Blob = kind {
+            return Ok(vec![]);
+        }
+        let data = match self.get(key)? {
+            Some(data) => data,
+            None => return Ok(vec![]),
+        };
+        let mut children = vec![];
+        collect_children(&rlp::Rlp::new(&data), kind, path, &mut children)?;
+        Ok(children)
+    }
+
+    fn refs(&self, counter: &[u8]) -> Result<u64, Error> {
+        match self.get(counter)? {
+            Some(data) => Ok(rlp::decode(&data)?),
+            None => Ok(0),
+        }
+    }
+
+    fn set_refs(&self, counter: &[u8], refs: u64) -> Result<(), Error> {
+        if refs == 0 {
+            self.remove(counter)
+        } else {
+            self.insert(counter.to_vec(), rlp::encode(&refs).to_vec())
+        }
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        self.db.get(key).map_err(|e| Error::DB(format!("{}", e)))
+    }
+
+    fn insert(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), Error> {
+        self.db.insert(key, value).map_err(|e| Error::DB(format!("{}", e)))
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<(), Error> {
+        self.db.remove(key).map_err(|e| Error::DB(format!("{}", e)))
+    }
+}
+
+fn refs_key(key: &[u8]) -> Vec<u8> {
+    let mut k = REFS_PREFIX.to_vec();
+    k.extend_from_slice(key);
+    k
+}
+
+/// Key of the reference count which decides whether the nodes referred by a
+/// node are counted. A world node is counted at each path, since the accounts
+/// in it depend on the path, and is kept while it is referred at any path.
+fn counter_key(key: &[u8], kind: NodeKind, path: &[u8]) -> Vec<u8> {
+    let mut k = refs_key(key);
+    if let NodeKind::World = kind {
+        k.push(path.len() as u8);
+        k.extend_from_slice(path);
+    }
+    k
+}
+
+/// Collect references of a node, which may be embedded in its parent.
+fn collect_children(node: &rlp::Rlp, kind: NodeKind, path: Vec<u8>, children: &mut Vec<Child>) -> Result<(), Error> {
+    if !node.is_list() {
+        return Ok(());
+    }
+    match node.item_count()? {
+        // Branch node, the value is never used since keys are in same length.
+        17 => {
+            for i in 0..16 {
+                let mut p = path.clone();
+                p.push(i as u8);
+                collect_child(&node.at(i)?, kind, p, children)?;
+            }
+        }
+        // Leaf or extension node, whose path is hex-prefix encoded.
+        2 => {
+            let encoded: Vec<u8> = node.val_at(0)?;
+            let mut p = path;
+            let is_leaf = match encoded.first() {
+                Some(first) => {
+                    if first & 0x10 != 0 {
+                        p.push(first & 0x0f);
+                    }
+                    first & 0x20 != 0
+                }
+                None => return Ok(()),
+            };
+            for b in &encoded[1..] {
+                p.push(b >> 4);
+                p.push(b & 0x0f);
+            }
+            if !is_leaf {
+                return collect_child(&node.at(1)?, kind, p, children);
+            }
+            if let (NodeKind::World, 40) = (kind, p.len()) {
+                let address = Address::from_slice(&nibbles_to_bytes(&p));
+                let account: Account = rlp::decode(&node.val_at::<Vec<u8>>(1)?)?;
+                let address_hash = H256::from_slice(&summary(&address[..]));
+                if account.storage_root != RLP_NULL {
+                    children.push((
+                        combine_key(&address_hash.0, &account.storage_root.0),
+                        NodeKind::Storage(address_hash),
+                        vec![],
+                    ));
+                }
+                for hash in [account.code_hash, account.abi_hash] {
+                    if hash != NIL_DATA {
+                        children.push((combine_key(&address_hash.0, &hash.0), NodeKind::Blob, vec![]));
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Collect a child of a node, which is either a hash or an embedded node.
+fn collect_child(child: &rlp::Rlp, kind: NodeKind, path: Vec<u8>, children: &mut Vec<Child>) -> Result<(), Error> {
+    if child.is_list() {
+        return collect_children(child, kind, path, children);
+    }
+    let hash = child.data()?;
+    if hash.len() != 32 {
+        return Ok(());
+    }
+    let key = match kind {
+        NodeKind::Storage(address_hash) => combine_key(&address_hash.0, hash),
+        _ => hash.to_vec(),
+    };
+    children.push((key, kind, path));
+    Ok(())
+}
+
+pub(crate) fn nibbles_to_bytes(nibbles: &[u8]) -> Vec<u8> {
+    nibbles
+        .chunks(2)
+        .map(|c| (c[0] << 4) | c.get(1).unwrap_or(&0))
+        .collect()
+}
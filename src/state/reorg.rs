@@ -0,0 +1,137 @@
+use cita_trie::DB;
+use ethereum_types::H256;
+use rlp::RlpStream;
+
+use crate::state::batch::WriteBatch;
+use crate::state::err::Error;
+
+const PREFIX_LEN: usize = 12;
+// Sequence of the next commit and roots which could be rolled back to, from
+// the oldest to the latest.
+const REORG_KEY: [u8; PREFIX_LEN] = [b'r', b'e', b'o', b'r', b'g', 0, 0, 0, 0, 0, 0, 0];
+// Prefix of the undo record of a commit, followed by the sequence of it.
+const UNDO_PREFIX: [u8; 4] = [b'u', b'n', b'd', b'o'];
+
+/// Reorg records the values overwritten by each commit, so the database could
+/// be rolled back to an earlier root when the chain reorgs.
+///
+/// Only keys written by batches are recorded. Keys written out of batches,
+/// like the latest era key written by `State::from_existing`, are left as
+/// they are. Reorg should not be used together with `Pruning` on the same
+/// database, since rolling back does not update reference counts.
+pub struct Reorg<'a, B> {
+    db: &'a B,
+}
+
+impl<'a, B: DB> Reorg<'a, B> {
+    pub fn new(db: &'a B) -> Self {
+        Reorg { db }
+    }
+
+    /// Roots which could be rolled back to, from the oldest to the latest.
+    pub fn roots(&self) -> Result<Vec<H256>, Error> {
+        Ok(self.history()?.1)
+    }
+
+    /// Write the batch and record the values overwritten by it, then drop
+    /// records older than the last `history` commits. The root before the
+    /// batch is kept as well, so it could be rolled back to.
+    pub fn commit(&self, parent: H256, batch: &WriteBatch, history: usize) -> Result<(), Error> {
+        let (next, mut roots) = self.history()?;
+        if roots.last() != Some(&parent) {
+            // The parent is committed out of Reorg, so the history before it
+            // could not be rolled back to anymore.
+            self.drop_records(first_record(next, &roots), roots.len().saturating_sub(1))?;
+            roots = vec![parent];
+        }
+
+        let mut undo = RlpStream::new_list(batch.changes.len());
+        for key in batch.changes.keys() {
+            match self.get(key)? {
+                Some(value) => undo.begin_list(2).append(key).append(&value),
+                None => undo.begin_list(1).append(key),
+            };
+        }
+        self.insert(undo_key(next), undo.out().to_vec())?;
+        batch.apply(self.db)?;
+        roots.push(batch.root);
+
+        let dropped = roots.len().saturating_sub(history + 1);
+        self.drop_records(first_record(next + 1, &roots), dropped)?;
+        roots.drain(..dropped);
+        self.set_history(next + 1, &roots)
+    }
+
+    /// Roll the database back to the root, which is the latest one of the
+    /// roots recorded if it occurs more than once. Undo records after it
+    /// are consumed.
+    pub fn rollback(&self, root: H256) -> Result<(), Error> {
+        let (mut next, mut roots) = self.history()?;
+        let position = roots.iter().rposition(|r| *r == root).ok_or(Error::NotFound)?;
+        while roots.len() > position + 1 {
+            next -= 1;
+            let data = self.get(&undo_key(next))?.ok_or(Error::NotFound)?;
+            for change in rlp::Rlp::new(&data).iter() {
+                let key: Vec<u8> = change.val_at(0)?;
+                match change.item_count()? {
+                    2 => self.insert(key, change.val_at(1)?)?,
+                    _ => self.remove(&key)?,
+                }
+            }
+            self.remove(&undo_key(next))?;
+            roots.pop();
+            // Update the history for each commit, so it matches the records left.
+            self.set_history(next, &roots)?;
+        }
+        Ok(())
+    }
+
+    // Remove `count` undo records, starting from the sequence `from`.
+    fn drop_records(&self, from: u64, count: usize) -> Result<(), Error> {
+        for seq in from..from + count as u64 {
+            self.remove(&undo_key(seq))?;
+        }
+        Ok(())
+    }
+
+    fn history(&self) -> Result<(u64, Vec<H256>), Error> {
+        match self.get(&REORG_KEY)? {
+            Some(data) => {
+                let rlp = rlp::Rlp::new(&data);
+                Ok((rlp.val_at(0)?, rlp.list_at(1)?))
+            }
+            None => Ok((0, vec![])),
+        }
+    }
+
+    fn set_history(&self, next: u64, roots: &[H256]) -> Result<(), Error> {
+        let mut stream = RlpStream::new_list(2);
+        stream.append(&next).append_list(roots);
+        self.insert(REORG_KEY.to_vec(), stream.out().to_vec())?;
+        self.db.flush().map_err(|e| Error::DB(format!("{}", e)))
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        self.db.get(key).map_err(|e| Error::DB(format!("{}", e)))
+    }
+
+    fn insert(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), Error> {
+        self.db.insert(key, value).map_err(|e| Error::DB(format!("{}", e)))
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<(), Error> {
+        self.db.remove(key).map_err(|e| Error::DB(format!("{}", e)))
+    }
+}
+
+// Returns the sequence of the oldest undo record. The records are for the
+// commits to the roots except the oldest one.
+fn first_record(next: u64, roots: &[H256]) -> u64 {
+    next - roots.len().saturating_sub(1) as u64
+}
+
+fn undo_key(seq: u64) -> Vec<u8> {
+    let mut k = UNDO_PREFIX.to_vec();
+    k.extend_from_slice(&seq.to_be_bytes());
+    k
+}
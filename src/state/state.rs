@@ -1,28 +1,61 @@
 use crate::common;
 use crate::common::hash;
-use crate::state::account::StateObject;
+use crate::state::account::{CodeState, StateObject};
 use crate::state::account_db::AccountDB;
+use crate::state::batch::{OverlayDB, WriteBatch};
 use crate::state::err::Error;
 use crate::state::object_entry::{ObjectStatus, StateObjectEntry};
+use crate::state::pruning::Pruning;
+use crate::state::reorg::Reorg;
 use cita_trie::{PatriciaTrie, Trie, DB};
 use ethereum_types::{Address, H256, U256};
-use hashbrown::hash_map::Entry;
 use hashbrown::{HashMap, HashSet};
 use log::debug;
-use rayon::prelude::{IntoParallelRefMutIterator, ParallelIterator};
+use rayon::prelude::{IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator};
 use std::cell::RefCell;
+use std::mem;
 use std::sync::Arc;
 
 const PREFIX_LEN: usize = 12;
 const LATEST_ERA_KEY: [u8; PREFIX_LEN] = [b'l', b'a', b's', b't', 0, 0, 0, 0, 0, 0, 0, 0];
 
+/// A change of the cache, which is recorded in the journal to be undone.
+#[derive(Clone)]
+enum StateChange {
+    Entry(Address, Option<Box<StateObjectEntry>>), // The replaced entry, for creating and killing accounts
+    Status(Address, ObjectStatus),
+    Balance(Address, U256),
+    Nonce(Address, U256),
+    Storage(Address, H256, Option<H256>), // The previous value in storage changes
+    Code(Address, CodeBackup),
+    Abi(Address, CodeBackup),
+}
+
+/// The code or abi replaced by `set_code` or `set_abi`.
+#[derive(Clone)]
+struct CodeBackup {
+    data: Vec<u8>,
+    hash: H256,
+    size: usize,
+    state: CodeState,
+}
+
+/// A handle of a checkpoint created by `State::checkpoint`. Handles are never
+/// reused, so a stale one is detected instead of reverting another checkpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Checkpoint(u64);
+
 /// State is the one who managers all accounts and states in Ethereum's system.
 pub struct State<B> {
     pub db: Arc<B>,
     pub root: H256,
     pub cache: RefCell<HashMap<Address, StateObjectEntry>>,
-    /// Checkpoints are used to revert to history
-    pub checkpoints: RefCell<Vec<HashMap<Address, Option<StateObjectEntry>>>>,
+    /// Checkpoints are used to revert to history. Each one is kept with the
+    /// length of the journal when it is created.
+    pub checkpoints: RefCell<Vec<(Checkpoint, usize)>>,
+    /// Changes of the cache made since the first checkpoint.
+    journal: RefCell<Vec<StateChange>>,
+    next_checkpoint: u64,
 }
 
 impl<B: DB> State<B> {
@@ -39,6 +72,8 @@ impl<B: DB> State<B> {
             root: H256::from(date),
             cache: RefCell::new(HashMap::new()),
             checkpoints: RefCell::new(Vec::new()),
+            journal: RefCell::new(Vec::new()),
+            next_checkpoint: 0,
         })
     }
 
@@ -57,6 +92,8 @@ impl<B: DB> State<B> {
             root,
             cache: RefCell::new(HashMap::new()),
             checkpoints: RefCell::new(Vec::new()),
+            journal: RefCell::new(Vec::new()),
+            next_checkpoint: 0,
         })
     }
 
@@ -99,7 +136,7 @@ impl<B: DB> State<B> {
     }
 
     /// Use a callback function to avoid clone data in caches.
-    fn call_with_cached<F, U>(&self, address: &Address, f: F) -> Result<U, Error>
+    pub(crate) fn call_with_cached<F, U>(&self, address: &Address, f: F) -> Result<U, Error>
     where
         F: Fn(Option<&StateObject>) -> U,
     {
@@ -197,39 +234,45 @@ impl<B: DB> State<B> {
             "state.set_storage address={:?} key={:?} value={:?}",
             address, key, value
         );
-        let state_object = self.get_state_object_or_default(address)?;
         let accdb = Arc::new(AccountDB::new(*address, self.db.clone()));
-        if state_object.get_storage(accdb, &key)? == Some(value) {
+        let current = self.call_with_cached(address, |a| match a {
+            Some(state_object) => state_object.get_storage(accdb.clone(), &key),
+            None => Ok(None),
+        })??;
+        if current == Some(value) {
             return Ok(());
         }
-
-        self.add_checkpoint(address);
-        if let Some(ref mut state_object_entry) = self.cache.borrow_mut().get_mut(address) {
-            match state_object_entry.state_object {
-                Some(ref mut state_object) => {
-                    state_object.set_storage(key, value);
-                    state_object_entry.status = ObjectStatus::Dirty;
-                }
-                None => panic!("state object always exist in cache."),
-            }
-        }
-        Ok(())
+        self.modify(address, |state_object| {
+            StateChange::Storage(*address, key, state_object.storage_changes.insert(key, value))
+        })
     }
 
     /// Set code for an account.
     pub fn set_code(&mut self, address: &Address, code: Vec<u8>) -> Result<(), Error> {
-        let mut state_object = self.get_state_object_or_default(address)?;
-        state_object.init_code(code);
-        self.insert_cache(address, StateObjectEntry::new_dirty(Some(state_object)));
-        Ok(())
+        self.modify(address, |state_object| {
+            let backup = CodeBackup {
+                data: mem::take(&mut state_object.code),
+                hash: state_object.code_hash,
+                size: state_object.code_size,
+                state: state_object.code_state,
+            };
+            state_object.init_code(code);
+            StateChange::Code(*address, backup)
+        })
     }
 
     /// Set abi for an account.
     pub fn set_abi(&mut self, address: &Address, abi: Vec<u8>) -> Result<(), Error> {
-        let mut state_object = self.get_state_object_or_default(address)?;
-        state_object.init_abi(abi);
-        self.insert_cache(address, StateObjectEntry::new_dirty(Some(state_object)));
-        Ok(())
+        self.modify(address, |state_object| {
+            let backup = CodeBackup {
+                data: mem::take(&mut state_object.abi),
+                hash: state_object.abi_hash,
+                size: state_object.abi_size,
+                state: state_object.abi_state,
+            };
+            state_object.init_abi(abi);
+            StateChange::Abi(*address, backup)
+        })
     }
 
     /// Add balance by incr for an account.
@@ -237,13 +280,14 @@ impl<B: DB> State<B> {
         if incr.is_zero() {
             return Ok(());
         }
-        let mut state_object = self.get_state_object_or_default(address)?;
-        if state_object.balance.overflowing_add(incr).1 {
+        if self.balance(address)?.overflowing_add(incr).1 {
             return Err(Error::BalanceError);
         }
-        state_object.add_balance(incr);
-        self.insert_cache(address, StateObjectEntry::new_dirty(Some(state_object)));
-        Ok(())
+        self.modify(address, |state_object| {
+            let balance = state_object.balance;
+            state_object.add_balance(incr);
+            StateChange::Balance(*address, balance)
+        })
     }
 
     /// Sub balance by decr for an account.
@@ -251,13 +295,14 @@ impl<B: DB> State<B> {
         if decr.is_zero() {
             return Ok(());
         }
-        let mut state_object = self.get_state_object_or_default(a)?;
-        if state_object.balance.overflowing_sub(decr).1 {
+        if self.balance(a)?.overflowing_sub(decr).1 {
             return Err(Error::BalanceError);
         }
-        state_object.sub_balance(decr);
-        self.insert_cache(a, StateObjectEntry::new_dirty(Some(state_object)));
-        Ok(())
+        self.modify(a, |state_object| {
+            let balance = state_object.balance;
+            state_object.sub_balance(decr);
+            StateChange::Balance(*a, balance)
+        })
     }
 
     /// Transfer balance from `from` to `to` by `by`.
@@ -269,30 +314,103 @@ impl<B: DB> State<B> {
 
     /// Increase nonce for an account.
     pub fn inc_nonce(&mut self, address: &Address) -> Result<(), Error> {
-        let mut state_object = self.get_state_object_or_default(address)?;
-        state_object.inc_nonce();
-        self.insert_cache(address, StateObjectEntry::new_dirty(Some(state_object)));
-        Ok(())
+        self.modify(address, |state_object| {
+            let nonce = state_object.nonce;
+            state_object.inc_nonce();
+            StateChange::Nonce(*address, nonce)
+        })
     }
 
-    /// Insert a state object entry into cache.
+    /// Insert a state object entry into cache. Dirty entries are recorded in
+    /// the journal with the entries they replace.
     fn insert_cache(&self, address: &Address, state_object_entry: StateObjectEntry) {
         let is_dirty = state_object_entry.is_dirty();
-        let old_entry = self
-            .cache
-            .borrow_mut()
-            .insert(*address, state_object_entry.clone_dirty());
-
+        let old_entry = self.cache.borrow_mut().insert(*address, state_object_entry);
         if is_dirty {
-            if let Some(checkpoint) = self.checkpoints.borrow_mut().last_mut() {
-                checkpoint.entry(*address).or_insert(old_entry);
-            }
+            self.record(StateChange::Entry(*address, old_entry.map(Box::new)));
+        }
+    }
+
+    /// Change an account in cache in place, which is created if not exists.
+    /// The closure returns the change to be recorded in the journal.
+    fn modify<F>(&mut self, address: &Address, f: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut StateObject) -> StateChange,
+    {
+        if !self.exist(address)? {
+            self.new_contract(address, U256::zero(), U256::zero(), vec![]);
+        }
+        let (change, status) = {
+            let mut cache = self.cache.borrow_mut();
+            let state_object_entry = cache.get_mut(address).expect("state object always exist in cache.");
+            let state_object = state_object_entry
+                .state_object
+                .as_mut()
+                .expect("state object always exist in cache.");
+            let change = f(state_object);
+            (
+                change,
+                mem::replace(&mut state_object_entry.status, ObjectStatus::Dirty),
+            )
+        };
+        self.record(change);
+        if status != ObjectStatus::Dirty {
+            self.record(StateChange::Status(*address, status));
+        }
+        Ok(())
+    }
+
+    // Changes made out of any checkpoint are never reverted.
+    fn record(&self, change: StateChange) {
+        if !self.checkpoints.borrow().is_empty() {
+            self.journal.borrow_mut().push(change);
         }
     }
 
     /// Flush the data from cache to database.
     pub fn commit(&mut self) -> Result<(), Error> {
+        let batch = self.commit_batch()?;
+        batch.apply(self.db.as_ref())
+    }
+
+    /// Commit the cache like `commit`, and delete trie nodes which are only
+    /// reachable from roots older than the last `history` ones. See `Pruning`.
+    pub fn commit_and_prune(&mut self, history: usize) -> Result<(), Error> {
+        let batch = self.commit_batch()?;
+        Pruning::new(self.db.as_ref()).commit(&batch, history)
+    }
+
+    /// Commit the cache like `commit`, and record the values overwritten, so
+    /// the database could be rolled back to the last `history` roots by
+    /// `rollback`. See `Reorg`.
+    pub fn commit_with_undo(&mut self, history: usize) -> Result<(), Error> {
+        let parent = self.root;
+        let batch = self.commit_batch()?;
+        Reorg::new(self.db.as_ref()).commit(parent, &batch, history)
+    }
+
+    /// Roll the database back to an earlier root committed by
+    /// `commit_with_undo`, and reset this state to it. The cache is dropped.
+    pub fn rollback(&mut self, root: H256) -> Result<(), Error> {
         assert!(self.checkpoints.borrow().is_empty());
+        Reorg::new(self.db.as_ref()).rollback(root)?;
+        if root == common::hash::RLP_NULL {
+            self.db
+                .insert(LATEST_ERA_KEY.to_vec(), [0x80].to_vec())
+                .map_err(|e| Error::DB(format!("{}", e)))?;
+        }
+        self.root = root;
+        self.cache.borrow_mut().clear();
+        self.journal.borrow_mut().clear();
+        Ok(())
+    }
+
+    /// Commit the cache like `commit`, but collect the writes into a batch
+    /// instead of writing the database. The caller should apply the batch
+    /// before accounts out of the cache are read, since the root is updated.
+    pub fn commit_batch(&mut self) -> Result<WriteBatch, Error> {
+        assert!(self.checkpoints.borrow().is_empty());
+        let overlay = Arc::new(OverlayDB::new(self.db.clone()));
         // Firstly, update account storage tree
         self.cache
             .borrow_mut()
@@ -304,7 +422,7 @@ impl<B: DB> State<B> {
 
                 if let Some(ref mut state_object) = entry.state_object {
                     // When operate on account element, AccountDB should be used
-                    let accdb = Arc::new(AccountDB::new(*address, self.db.clone()));
+                    let accdb = Arc::new(AccountDB::new(*address, overlay.clone()));
                     state_object.commit_storage(Arc::clone(&accdb))?;
                     state_object.commit_code(Arc::clone(&accdb))?;
                     state_object.commit_abi(Arc::clone(&accdb))?;
@@ -314,7 +432,7 @@ impl<B: DB> State<B> {
             .collect::<Result<(), Error>>()?;
 
         // Secondly, update the world state tree
-        let mut trie = PatriciaTrie::from(Arc::clone(&self.db), Arc::new(hash::get_hasher()), &self.root.0)?;
+        let mut trie = PatriciaTrie::from(Arc::clone(&overlay), Arc::new(hash::get_hasher()), &self.root.0)?;
 
         let key_values = self
             .cache
@@ -337,71 +455,196 @@ impl<B: DB> State<B> {
         let mut date = [0; 32];
         date.copy_from_slice(trie.root()?.as_slice());
         self.root = H256::from(date);
-        self.db.flush().map_err(|e| Error::DB(format!("{}", e)))
+        Ok(overlay.take_batch(self.root))
     }
 
-    /// Create a recoverable checkpoint of this state. Return the checkpoint index.
-    pub fn checkpoint(&mut self) -> usize {
+    /// Returns the state root as if the cache were committed. The tries are
+    /// updated in memory, so neither the database nor the cache is changed.
+    pub fn compute_root(&self) -> Result<H256, Error> {
+        let overlay = Arc::new(OverlayDB::new(self.db.clone()));
+        let key_values = self
+            .cache
+            .borrow()
+            .par_iter()
+            .filter(|(_, a)| a.is_dirty())
+            .map(|(address, entry)| match entry.state_object {
+                Some(ref state_object) => {
+                    let accdb = Arc::new(AccountDB::new(*address, overlay.clone()));
+                    let mut account = state_object.account();
+                    account.storage_root = state_object.compute_storage_root(accdb)?;
+                    Ok((address.0.to_vec(), rlp::encode(&account).to_vec()))
+                }
+                None => Ok((address.0.to_vec(), vec![])),
+            })
+            .collect::<Result<Vec<(Vec<u8>, Vec<u8>)>, Error>>()?;
+
+        let mut trie = PatriciaTrie::from(overlay, Arc::new(hash::get_hasher()), &self.root.0)?;
+        for (key, value) in key_values.into_iter() {
+            trie.insert(key, value)?;
+        }
+        Ok(H256::from_slice(trie.root()?.as_slice()))
+    }
+
+    /// Create a recoverable checkpoint of this state. Return the handle of it.
+    pub fn checkpoint(&mut self) -> Checkpoint {
         debug!("state.checkpoint");
-        let mut checkpoints = self.checkpoints.borrow_mut();
-        let index = checkpoints.len();
-        checkpoints.push(HashMap::new());
-        index
+        let checkpoint = Checkpoint(self.next_checkpoint);
+        self.next_checkpoint += 1;
+        self.checkpoints
+            .borrow_mut()
+            .push((checkpoint, self.journal.borrow().len()));
+        checkpoint
     }
 
-    fn add_checkpoint(&self, address: &Address) {
-        if let Some(ref mut checkpoint) = self.checkpoints.borrow_mut().last_mut() {
-            checkpoint
-                .entry(*address)
-                .or_insert_with(|| self.cache.borrow().get(address).map(StateObjectEntry::clone_dirty));
+    /// Keep the changes since the checkpoint and discard it. The checkpoint
+    /// must be the last one.
+    pub fn commit_to(&mut self, checkpoint: Checkpoint) -> Result<(), Error> {
+        self.ensure_last_checkpoint(checkpoint)?;
+        self.discard_checkpoint();
+        Ok(())
+    }
+
+    /// Undo the changes since the checkpoint and discard it. The checkpoint
+    /// must be the last one.
+    pub fn revert_to(&mut self, checkpoint: Checkpoint) -> Result<(), Error> {
+        self.ensure_last_checkpoint(checkpoint)?;
+        self.revert_checkpoint();
+        Ok(())
+    }
+
+    fn ensure_last_checkpoint(&self, checkpoint: Checkpoint) -> Result<(), Error> {
+        match self.checkpoints.borrow().last() {
+            Some((last, _)) if *last == checkpoint => Ok(()),
+            _ => Err(Error::CheckpointOutOfOrder),
         }
     }
 
     /// Merge last checkpoint with previous.
     pub fn discard_checkpoint(&mut self) {
-        let last = self.checkpoints.borrow_mut().pop();
-        if let Some(mut checkpoint) = last {
-            if let Some(prev) = self.checkpoints.borrow_mut().last_mut() {
-                if prev.is_empty() {
-                    *prev = checkpoint;
-                } else {
-                    for (k, v) in checkpoint.drain() {
-                        prev.entry(k).or_insert(v);
-                    }
-                }
-            }
+        let mut checkpoints = self.checkpoints.borrow_mut();
+        checkpoints.pop();
+        if checkpoints.is_empty() {
+            self.journal.borrow_mut().clear();
         }
     }
 
-    /// Revert to the last checkpoint and discard it.
+    /// Revert to the last checkpoint and discard it. Changes in the journal
+    /// are undone in reverse order.
     pub fn revert_checkpoint(&mut self) {
         debug!("state.revert_checkpoint");
-        if let Some(mut last) = self.checkpoints.borrow_mut().pop() {
-            for (k, v) in last.drain() {
-                match v {
-                    Some(v) => match self.cache.borrow_mut().entry(k) {
-                        Entry::Occupied(mut e) => {
-                            // Merge checkpointed changes back into the main account
-                            // storage preserving the cache.
-                            e.get_mut().merge(v);
-                        }
-                        Entry::Vacant(e) => {
-                            e.insert(v);
-                        }
-                    },
-                    None => {
-                        if let Entry::Occupied(e) = self.cache.borrow_mut().entry(k) {
-                            if e.get().is_dirty() {
-                                e.remove();
-                            }
-                        }
-                    }
-                }
+        let n = match self.checkpoints.borrow_mut().pop() {
+            Some((_, n)) => n,
+            None => return,
+        };
+        let changes: Vec<StateChange> = self.journal.borrow_mut().drain(n..).collect();
+        let mut cache = self.cache.borrow_mut();
+        for change in changes.into_iter().rev() {
+            undo(&mut cache, change);
+        }
+    }
+
+    /// Returns the entries of accounts changed since the checkpoint as they
+    /// were when it was created, or none if they were not in the cache. They
+    /// are copied from the cache, and the journal is undone on the copies.
+    pub(crate) fn entries_at(
+        &self,
+        checkpoint: Checkpoint,
+    ) -> Result<HashMap<Address, Option<StateObjectEntry>>, Error> {
+        let n = match self.checkpoints.borrow().iter().find(|(c, _)| *c == checkpoint) {
+            Some((_, n)) => *n,
+            None => return Err(Error::CheckpointOutOfOrder),
+        };
+        let journal = self.journal.borrow();
+        let touched: HashSet<Address> = journal[n..].iter().map(StateChange::address).collect();
+        let mut entries: HashMap<Address, StateObjectEntry> = {
+            let cache = self.cache.borrow();
+            touched
+                .iter()
+                .filter_map(|address| cache.get(address).map(|entry| (*address, entry.clone_dirty())))
+                .collect()
+        };
+        for change in journal[n..].iter().rev() {
+            undo(&mut entries, change.clone());
+        }
+        Ok(touched
+            .into_iter()
+            .map(|address| (address, entries.remove(&address)))
+            .collect())
+    }
+}
+
+impl StateChange {
+    fn address(&self) -> Address {
+        match self {
+            StateChange::Entry(address, _)
+            | StateChange::Status(address, _)
+            | StateChange::Balance(address, _)
+            | StateChange::Nonce(address, _)
+            | StateChange::Storage(address, _, _)
+            | StateChange::Code(address, _)
+            | StateChange::Abi(address, _) => *address,
+        }
+    }
+}
+
+// Undo a change recorded in the journal.
+fn undo(cache: &mut HashMap<Address, StateObjectEntry>, change: StateChange) {
+    match change {
+        StateChange::Entry(address, Some(entry)) => {
+            cache.insert(address, *entry);
+        }
+        StateChange::Entry(address, None) => {
+            cache.remove(&address);
+        }
+        StateChange::Status(address, status) => {
+            if let Some(entry) = cache.get_mut(&address) {
+                entry.status = status;
+            }
+        }
+        StateChange::Balance(address, balance) => {
+            if let Some(state_object) = cached_state_object(cache, &address) {
+                state_object.balance = balance;
+            }
+        }
+        StateChange::Nonce(address, nonce) => {
+            if let Some(state_object) = cached_state_object(cache, &address) {
+                state_object.nonce = nonce;
+            }
+        }
+        StateChange::Storage(address, key, value) => {
+            if let Some(state_object) = cached_state_object(cache, &address) {
+                match value {
+                    Some(value) => state_object.storage_changes.insert(key, value),
+                    None => state_object.storage_changes.remove(&key),
+                };
+            }
+        }
+        StateChange::Code(address, backup) => {
+            if let Some(state_object) = cached_state_object(cache, &address) {
+                state_object.code = backup.data;
+                state_object.code_hash = backup.hash;
+                state_object.code_size = backup.size;
+                state_object.code_state = backup.state;
+            }
+        }
+        StateChange::Abi(address, backup) => {
+            if let Some(state_object) = cached_state_object(cache, &address) {
+                state_object.abi = backup.data;
+                state_object.abi_hash = backup.hash;
+                state_object.abi_size = backup.size;
+                state_object.abi_state = backup.state;
             }
         }
     }
 }
 
+fn cached_state_object<'a>(
+    cache: &'a mut HashMap<Address, StateObjectEntry>,
+    address: &Address,
+) -> Option<&'a mut StateObject> {
+    cache.get_mut(address).and_then(|entry| entry.state_object.as_mut())
+}
+
 pub trait StateObjectInfo {
     fn nonce(&mut self, a: &Address) -> Result<U256, Error>;
 
@@ -868,6 +1111,207 @@ mod tests {
         assert_eq!(orig_root, state.root);
     }
 
+    #[test]
+    fn checkpoint_journal() {
+        let mut state = get_temp_state();
+        let a = Address::from_low_u64_be(1000);
+        let k = H256::from_low_u64_be(1);
+        state.new_contract(&a, 5.into(), 1.into(), vec![1, 2, 3]);
+        state.set_abi(&a, vec![4, 5, 6]).unwrap();
+        state.set_storage(&a, k, H256::from_low_u64_be(1)).unwrap();
+        state.commit().unwrap();
+        let orig_root = state.root;
+
+        state.checkpoint(); // c1
+        state.inc_nonce(&a).unwrap();
+        state.set_code(&a, vec![7]).unwrap();
+        state.checkpoint(); // c2
+        state.set_abi(&a, vec![]).unwrap();
+        state.set_storage(&a, k, H256::from_low_u64_be(2)).unwrap();
+        state.kill_contract(&a);
+        state.new_contract(&a, 1.into(), 0.into(), vec![]);
+        state.revert_checkpoint(); // revert c2
+        assert_eq!(state.nonce(&a).unwrap(), 2.into());
+        assert_eq!(state.code(&a).unwrap(), vec![7]);
+        assert_eq!(state.abi(&a).unwrap(), vec![4, 5, 6]);
+        assert_eq!(state.get_storage(&a, &k).unwrap(), H256::from_low_u64_be(1));
+
+        state.revert_checkpoint(); // revert c1
+        assert_eq!(state.nonce(&a).unwrap(), 1.into());
+        assert_eq!(state.code(&a).unwrap(), vec![1, 2, 3]);
+        assert!(!state.cache.borrow()[&a].is_dirty());
+        state.commit().unwrap();
+        assert_eq!(orig_root, state.root);
+    }
+
+    #[test]
+    fn checkpoint_handles() {
+        let mut state = get_temp_state();
+        let a = Address::zero();
+
+        let c1 = state.checkpoint();
+        state.add_balance(&a, U256::from(1u64)).unwrap();
+        let c2 = state.checkpoint();
+        state.add_balance(&a, U256::from(2u64)).unwrap();
+        assert_eq!(state.revert_to(c1), Err(Error::CheckpointOutOfOrder));
+        assert_eq!(state.commit_to(c1), Err(Error::CheckpointOutOfOrder));
+        assert_eq!(state.balance(&a).unwrap(), U256::from(3u64));
+
+        state.revert_to(c2).unwrap();
+        assert_eq!(state.balance(&a).unwrap(), U256::from(1u64));
+        // A handle is never reused.
+        let c3 = state.checkpoint();
+        assert_ne!(c2, c3);
+        assert_eq!(state.revert_to(c2), Err(Error::CheckpointOutOfOrder));
+        state.commit_to(c3).unwrap();
+        state.commit_to(c1).unwrap();
+        assert!(state.checkpoints.borrow().is_empty());
+        assert_eq!(state.balance(&a).unwrap(), U256::from(1u64));
+    }
+
+    #[test]
+    fn commit_batch() {
+        let a = Address::from_low_u64_be(1000);
+        let k = H256::from_low_u64_be(1);
+        let mut expected = get_temp_state();
+        expected.new_contract(&a, 5.into(), 1.into(), vec![1, 2, 3]);
+        expected.set_storage(&a, k, H256::from_low_u64_be(1)).unwrap();
+        expected.commit().unwrap();
+
+        let mut state = get_temp_state();
+        state.new_contract(&a, 5.into(), 1.into(), vec![1, 2, 3]);
+        state.set_storage(&a, k, H256::from_low_u64_be(1)).unwrap();
+        let batch = state.commit_batch().unwrap();
+        assert_eq!(batch.root, expected.root);
+        assert_eq!(state.root, expected.root);
+        // Nothing is written before the batch is applied.
+        assert!(!state.db.contains(&batch.root.0).unwrap());
+        batch.apply(state.db.as_ref()).unwrap();
+
+        let mut state = State::from_existing(state.db, batch.root).unwrap();
+        assert_eq!(state.code(&a).unwrap(), vec![1, 2, 3]);
+        assert_eq!(state.get_storage(&a, &k).unwrap(), H256::from_low_u64_be(1));
+    }
+
+    #[test]
+    fn compute_root() {
+        let mut state = get_temp_state();
+        let a = Address::from_low_u64_be(1000);
+        let b = Address::from_low_u64_be(2000);
+        let k = H256::from_low_u64_be(1);
+        state.new_contract(&a, 5.into(), 1.into(), vec![1, 2, 3]);
+        state.new_contract(&b, 5.into(), 1.into(), vec![]);
+        state.commit().unwrap();
+
+        state.set_storage(&a, k, H256::from_low_u64_be(1)).unwrap();
+        state.add_balance(&a, 1.into()).unwrap();
+        state.kill_contract(&b);
+        let root = state.compute_root().unwrap();
+        assert!(!state.db.contains(&root.0).unwrap());
+        assert!(state.cache.borrow()[&a].is_dirty());
+        assert_eq!(state.get_storage(&a, &k).unwrap(), H256::from_low_u64_be(1));
+
+        state.commit().unwrap();
+        assert_eq!(state.root, root);
+        assert_eq!(state.compute_root().unwrap(), root);
+    }
+
+    #[test]
+    fn commit_and_prune() {
+        let db = Arc::new(MemoryDB::new(true));
+        let mut state = State::new(db.clone()).unwrap();
+        let a = Address::from_str("1000000000000000000000000000000000000000").unwrap();
+        let b = Address::from_str("2000000000000000000000000000000000000000").unwrap();
+        let c = Address::from_low_u64_be(3000);
+        let k = H256::from_low_u64_be(1);
+        // Account a and b are the same, so they share the leaf node.
+        state.new_contract(&a, 5.into(), 1.into(), vec![]);
+        state.new_contract(&b, 5.into(), 1.into(), vec![]);
+        state.new_contract(&c, 5.into(), 1.into(), vec![1, 2, 3]);
+        state.set_storage(&c, k, H256::from_low_u64_be(1)).unwrap();
+        state.commit_and_prune(2).unwrap();
+
+        let mut roots = vec![state.root];
+        for i in 2..6 {
+            state.add_balance(&a, 1.into()).unwrap();
+            state.set_storage(&c, k, H256::from_low_u64_be(i)).unwrap();
+            state.commit_and_prune(2).unwrap();
+            roots.push(state.root);
+        }
+        assert_eq!(Pruning::new(db.as_ref()).roots().unwrap(), roots[3..].to_vec());
+        for root in roots[..3].iter() {
+            assert!(State::from_existing(db.clone(), *root).is_err());
+        }
+        for (i, root) in roots.iter().enumerate().skip(3) {
+            let mut state = State::from_existing(db.clone(), *root).unwrap();
+            assert_eq!(state.balance(&a).unwrap(), U256::from(5 + i));
+            assert_eq!(state.balance(&b).unwrap(), U256::from(5));
+            assert_eq!(state.code(&c).unwrap(), vec![1, 2, 3]);
+            assert_eq!(state.get_storage(&c, &k).unwrap(), H256::from_low_u64_be(i as u64 + 1));
+        }
+    }
+
+    #[test]
+    fn commit_and_prune_shared_contracts() {
+        let db = Arc::new(MemoryDB::new(true));
+        let mut state = State::new(db.clone()).unwrap();
+        let a = Address::from_str("1000000000000000000000000000000000000000").unwrap();
+        let b = Address::from_str("2000000000000000000000000000000000000000").unwrap();
+        let k = H256::from_low_u64_be(1);
+        // Contract a and b are the same, so they share the leaf node under
+        // the root branch, but not their storage and code.
+        for address in [a, b] {
+            state.new_contract(&address, 5.into(), 1.into(), vec![1, 2, 3]);
+            state.set_storage(&address, k, H256::from_low_u64_be(1)).unwrap();
+        }
+        state.commit_and_prune(1).unwrap();
+
+        state.add_balance(&a, 1.into()).unwrap();
+        state.commit_and_prune(1).unwrap();
+        state.add_balance(&b, 1.into()).unwrap();
+        state.commit_and_prune(1).unwrap();
+
+        let mut state = State::from_existing(db.clone(), state.root).unwrap();
+        for address in [a, b] {
+            assert_eq!(state.balance(&address).unwrap(), U256::from(6));
+            assert_eq!(state.code(&address).unwrap(), vec![1, 2, 3]);
+            assert_eq!(state.get_storage(&address, &k).unwrap(), H256::from_low_u64_be(1));
+        }
+    }
+
+    #[test]
+    fn commit_with_undo() {
+        let db = Arc::new(MemoryDB::new(true));
+        let mut state = State::new(db.clone()).unwrap();
+        let a = Address::from_low_u64_be(1000);
+        let k = H256::from_low_u64_be(1);
+        let mut roots = vec![state.root];
+        for i in 1..5 {
+            state.add_balance(&a, 1.into()).unwrap();
+            state.set_storage(&a, k, H256::from_low_u64_be(i)).unwrap();
+            state.commit_with_undo(3).unwrap();
+            roots.push(state.root);
+        }
+        assert_eq!(Reorg::new(db.as_ref()).roots().unwrap(), roots[1..].to_vec());
+        assert_eq!(state.rollback(roots[0]), Err(Error::NotFound));
+
+        state.rollback(roots[2]).unwrap();
+        assert_eq!(state.balance(&a).unwrap(), U256::from(2));
+        assert_eq!(state.get_storage(&a, &k).unwrap(), H256::from_low_u64_be(2));
+        assert!(State::from_existing(db.clone(), roots[4]).is_err());
+        assert_eq!(Reorg::new(db.as_ref()).roots().unwrap(), roots[1..3].to_vec());
+
+        // Commit another branch on the root rolled back to.
+        state.add_balance(&a, 10.into()).unwrap();
+        state.commit_with_undo(3).unwrap();
+        let fork = state.root;
+        state.rollback(roots[1]).unwrap();
+        assert!(State::from_existing(db.clone(), fork).is_err());
+        let mut state = State::from_existing(db.clone(), roots[1]).unwrap();
+        assert_eq!(state.balance(&a).unwrap(), U256::from(1));
+        assert_eq!(state.get_storage(&a, &k).unwrap(), H256::from_low_u64_be(1));
+    }
+
     #[test]
     fn get_account_proof() {
         let mut state = get_temp_state();
@@ -2,7 +2,7 @@ use std::cell::RefCell;
 use std::str::FromStr;
 use std::sync::Arc;
 
-use ethereum_types::{Address, U256};
+use ethereum_types::{Address, H256, U256};
 
 use cita_vm::state::StateObjectInfo;
 
@@ -39,6 +39,7 @@ fn test_state_misc00() {
         gas_limit: 80000,
         gas_price: U256::from(1),
         input: hex::decode("").unwrap(),
+        ..Default::default()
     };
     let _ = cita_vm::exec(
         block_data_provider.clone(),
@@ -62,3 +63,1645 @@ fn test_state_misc00() {
         .unwrap();
     assert_eq!(b, U256::from(100_000));
 }
+
+#[test]
+fn test_dry_run_create() {
+    let db = Arc::new(cita_vm::state::MemoryDB::new(false));
+    let mut state = cita_vm::state::State::new(db).unwrap();
+    let sender = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+    state.new_contract(&sender, U256::from(1_000_000), U256::from(1), vec![]);
+    state.commit().unwrap();
+    let root0 = state.root;
+
+    let block_data_provider: Arc<dyn cita_vm::BlockDataProvider> = Arc::new(cita_vm::BlockDataProviderMock::default());
+    let state_data_provider = Arc::new(RefCell::new(state));
+    let context = cita_vm::evm::Context::default();
+    let config = cita_vm::Config::default();
+
+    // Init code emits a LOG0 and deploys runtime code 0x6001.
+    let tx = cita_vm::Transaction {
+        from: sender,
+        to: None,
+        value: U256::from(0),
+        nonce: U256::from(1),
+        gas_limit: 100_000,
+        gas_price: U256::from(1),
+        input: hex::decode("6160016000526002601ea06002601ef3").unwrap(),
+        ..Default::default()
+    };
+    let r = cita_vm::exec_dry_run_create(
+        block_data_provider.clone(),
+        state_data_provider.clone(),
+        context.clone(),
+        config.clone(),
+        tx.clone(),
+        None,
+    )
+    .unwrap();
+    assert_eq!(
+        r.address,
+        cita_vm::create_address_from_address_and_nonce(&sender, &U256::from(1))
+    );
+    assert_eq!(r.code, vec![0x60, 0x01]);
+    assert_eq!(r.logs.len(), 1);
+    assert!(r.gas_used > 53000);
+
+    // Nothing is persisted: the balance is untouched and the root stays the same.
+    assert_eq!(
+        state_data_provider.borrow_mut().balance(&sender).unwrap(),
+        U256::from(1_000_000)
+    );
+    state_data_provider.borrow_mut().commit().unwrap();
+    assert_eq!(state_data_provider.borrow().root, root0);
+
+    // Real execution deploys to the very same address.
+    let r2 = cita_vm::exec(block_data_provider, state_data_provider, context, config, tx).unwrap();
+    match r2 {
+        cita_vm::evm::InterpreterResult::Create(code, gas_left, _, address) => {
+            assert_eq!(address, r.address);
+            assert_eq!(code, r.code);
+            assert_eq!(100_000 - gas_left, r.gas_used);
+        }
+        _ => panic!("create expected"),
+    }
+}
+
+#[test]
+fn test_create_access_list() {
+    let db = Arc::new(cita_vm::state::MemoryDB::new(false));
+    let mut state = cita_vm::state::State::new(db).unwrap();
+    let sender = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+    let contract = Address::from_str("0x2000000000000000000000000000000000000000").unwrap();
+    let other = Address::from_str("0x3000000000000000000000000000000000000000").unwrap();
+    state.new_contract(&sender, U256::from(1_000_000), U256::from(1), vec![]);
+    // SLOAD slot 1, BALANCE of 0x30..00 and CALL the identity precompile.
+    state.new_contract(
+        &contract,
+        U256::from(0),
+        U256::from(1),
+        hex::decode("60015450733000000000000000000000000000000000000000315060006000600060006000600461fffff15000")
+            .unwrap(),
+    );
+    state.commit().unwrap();
+    let root0 = state.root;
+
+    let block_data_provider: Arc<dyn cita_vm::BlockDataProvider> = Arc::new(cita_vm::BlockDataProviderMock::default());
+    let state_data_provider = Arc::new(RefCell::new(state));
+    let context = cita_vm::evm::Context::default();
+    let config = cita_vm::Config::default();
+
+    let tx = cita_vm::Transaction {
+        from: sender,
+        to: Some(contract),
+        value: U256::from(0),
+        nonce: U256::from(1),
+        gas_limit: 100_000,
+        gas_price: U256::from(1),
+        input: vec![],
+        ..Default::default()
+    };
+    let r = cita_vm::exec_create_access_list(
+        block_data_provider.clone(),
+        state_data_provider.clone(),
+        context.clone(),
+        config.clone(),
+        tx.clone(),
+    )
+    .unwrap();
+    // The sender, the recipient and the precompile are excluded, even if
+    // their storage is touched.
+    assert_eq!(
+        r.access_list,
+        vec![cita_vm::AccessListItem {
+            address: other,
+            storage_keys: vec![],
+        }]
+    );
+    assert_eq!(r.gas_used, r.gas_used_without_access_list + 2400);
+    state_data_provider.borrow_mut().commit().unwrap();
+    assert_eq!(state_data_provider.borrow().root, root0);
+
+    // Executing with and without the access list costs exactly the reported gas.
+    let gas_used = |access_list: Vec<cita_vm::AccessListItem>| {
+        let tx = cita_vm::Transaction {
+            access_list,
+            ..tx.clone()
+        };
+        match cita_vm::exec(
+            block_data_provider.clone(),
+            state_data_provider.clone(),
+            context.clone(),
+            config.clone(),
+            tx,
+        )
+        .unwrap()
+        {
+            cita_vm::evm::InterpreterResult::Normal(_, gas_left, _) => 100_000 - gas_left,
+            _ => panic!("normal result expected"),
+        }
+    };
+    assert_eq!(gas_used(vec![]), r.gas_used_without_access_list);
+    assert_eq!(gas_used(r.access_list.clone()), r.gas_used);
+}
+
+#[test]
+fn test_gas_schedule() {
+    let db = Arc::new(cita_vm::state::MemoryDB::new(false));
+    let mut state = cita_vm::state::State::new(db).unwrap();
+    let sender = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+    let receiver = Address::from_str("0x2000000000000000000000000000000000000000").unwrap();
+    state.new_contract(&sender, U256::from(1_000_000), U256::from(1), vec![]);
+    state.commit().unwrap();
+
+    let block_data_provider: Arc<dyn cita_vm::BlockDataProvider> = Arc::new(cita_vm::BlockDataProviderMock::default());
+    let state_data_provider = Arc::new(RefCell::new(state));
+    let context = cita_vm::evm::Context::default();
+
+    // 100 non-zero bytes: 21000 + 16 * 100 intrinsic, 21000 + 10 * 4 * 100 floor.
+    let tx = cita_vm::Transaction {
+        from: sender,
+        to: Some(receiver),
+        value: U256::from(0),
+        nonce: U256::from(1),
+        gas_limit: 30_000,
+        gas_price: U256::from(1),
+        input: vec![0xff; 100],
+        ..Default::default()
+    };
+    let gas_used = |schedule: cita_vm::GasSchedule, tx: cita_vm::Transaction| {
+        let config = cita_vm::Config {
+            gas_schedule: schedule,
+            ..Default::default()
+        };
+        match cita_vm::exec(
+            block_data_provider.clone(),
+            state_data_provider.clone(),
+            context.clone(),
+            config,
+            tx,
+        ) {
+            Ok(cita_vm::evm::InterpreterResult::Normal(_, gas_left, _)) => Ok(30_000 - gas_left),
+            Ok(_) => panic!("normal result expected"),
+            Err(e) => Err(e),
+        }
+    };
+    assert_eq!(gas_used(cita_vm::GasSchedule::default(), tx.clone()).unwrap(), 21_000);
+    assert_eq!(
+        gas_used(cita_vm::GasSchedule::constantinople(), tx.clone()).unwrap(),
+        27_800
+    );
+    assert_eq!(gas_used(cita_vm::GasSchedule::istanbul(), tx.clone()).unwrap(), 22_600);
+    assert_eq!(gas_used(cita_vm::GasSchedule::prague(), tx.clone()).unwrap(), 25_000);
+    assert_eq!(
+        state_data_provider.borrow_mut().balance(&sender).unwrap(),
+        U256::from(1_000_000 - 21_000 - 27_800 - 22_600 - 25_000)
+    );
+
+    // Every address and storage key in the access list is paid. See: EIP 2930
+    let access_list = vec![cita_vm::AccessListItem {
+        address: receiver,
+        storage_keys: vec![H256::zero(), H256::from_low_u64_be(1)],
+    }];
+    let with_access_list = cita_vm::Transaction {
+        access_list,
+        ..tx.clone()
+    };
+    assert_eq!(
+        gas_used(cita_vm::GasSchedule::istanbul(), with_access_list.clone()).unwrap(),
+        22_600 + 2400 + 2 * 1900
+    );
+    let free = cita_vm::GasSchedule {
+        access_list_address: 0,
+        access_list_storage_key: 0,
+        ..cita_vm::GasSchedule::istanbul()
+    };
+    assert_eq!(gas_used(free, with_access_list).unwrap(), 22_600);
+
+    // The gas limit must cover the floor.
+    let tx = cita_vm::Transaction {
+        gas_limit: 24_000,
+        ..tx
+    };
+    assert!(gas_used(cita_vm::GasSchedule::istanbul(), tx.clone()).is_ok());
+    match gas_used(cita_vm::GasSchedule::prague(), tx) {
+        Err(cita_vm::Error::NotEnoughBaseGas) => {}
+        _ => panic!("NotEnoughBaseGas expected"),
+    }
+}
+
+#[test]
+fn test_code_size_limits() {
+    let db = Arc::new(cita_vm::state::MemoryDB::new(false));
+    let mut state = cita_vm::state::State::new(db).unwrap();
+    let sender = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+    state.new_contract(&sender, U256::from(1_000_000), U256::from(1), vec![]);
+    state.commit().unwrap();
+
+    let block_data_provider: Arc<dyn cita_vm::BlockDataProvider> = Arc::new(cita_vm::BlockDataProviderMock::default());
+    let state_data_provider = Arc::new(RefCell::new(state));
+    let context = cita_vm::evm::Context::default();
+    let mut config = cita_vm::Config::default();
+    // The limits of EIP 170 and EIP 3860 apply by default.
+    assert_eq!(config.evm_cfg.max_create_code_size, 24576);
+    assert_eq!(config.evm_cfg.max_initcode_size, 49152);
+    assert_eq!(
+        cita_vm::evm::InterpreterConf::from_spec(cita_vm::evm::Spec::London).max_initcode_size,
+        u64::MAX
+    );
+    config.evm_cfg.max_create_code_size = 1;
+    config.evm_cfg.max_initcode_size = 16;
+
+    // Init code deploys runtime code 0x6001, which is 2 bytes.
+    let tx = cita_vm::Transaction {
+        from: sender,
+        to: None,
+        value: U256::from(0),
+        nonce: U256::from(1),
+        gas_limit: 100_000,
+        gas_price: U256::from(1),
+        input: hex::decode("6160016000526002601ea06002601ef3").unwrap(),
+        ..Default::default()
+    };
+
+    // Too large initcode makes the transaction invalid, nothing is charged.
+    let too_large = cita_vm::Transaction {
+        input: [tx.input.clone(), vec![0x00]].concat(),
+        ..tx.clone()
+    };
+    match cita_vm::exec(
+        block_data_provider.clone(),
+        state_data_provider.clone(),
+        context.clone(),
+        config.clone(),
+        too_large,
+    ) {
+        Err(cita_vm::Error::ExccedMaxInitCodeSize) => {}
+        _ => panic!("ExccedMaxInitCodeSize expected"),
+    }
+    assert_eq!(
+        state_data_provider.borrow_mut().balance(&sender).unwrap(),
+        U256::from(1_000_000)
+    );
+
+    // Too large deployed code is an exceptional halt, all gas is consumed.
+    match cita_vm::exec(
+        block_data_provider.clone(),
+        state_data_provider.clone(),
+        context.clone(),
+        config.clone(),
+        tx.clone(),
+    ) {
+        Err(cita_vm::Error::ExccedMaxCodeSize) => {}
+        _ => panic!("ExccedMaxCodeSize expected"),
+    }
+    assert_eq!(
+        state_data_provider.borrow_mut().balance(&sender).unwrap(),
+        U256::from(900_000)
+    );
+
+    // Every word of the initcode is paid.
+    config.evm_cfg.max_create_code_size = 2;
+    let r0 = cita_vm::exec_dry_run_create(
+        block_data_provider.clone(),
+        state_data_provider.clone(),
+        context.clone(),
+        config.clone(),
+        tx.clone(),
+        None,
+    )
+    .unwrap();
+    config.gas_schedule.initcode_word = 2;
+    let r1 = cita_vm::exec_dry_run_create(block_data_provider, state_data_provider, context, config, tx, None).unwrap();
+    assert_eq!(r1.gas_used, r0.gas_used + 2);
+}
+
+#[test]
+fn test_eof_create() {
+    let db = Arc::new(cita_vm::state::MemoryDB::new(false));
+    let mut state = cita_vm::state::State::new(db).unwrap();
+    let sender = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+    state.new_contract(&sender, U256::from(1_000_000), U256::from(1), vec![]);
+    state.commit().unwrap();
+
+    let block_data_provider: Arc<dyn cita_vm::BlockDataProvider> = Arc::new(cita_vm::BlockDataProviderMock::default());
+    let state_data_provider = Arc::new(RefCell::new(state));
+    let context = cita_vm::evm::Context::default();
+    let mut config = cita_vm::Config::default();
+
+    // Legacy init code deploys 0xef, which is rejected by default and since
+    // London. See: EIP 3541
+    let tx = cita_vm::Transaction {
+        from: sender,
+        to: None,
+        value: U256::from(0),
+        nonce: U256::from(1),
+        gas_limit: 100_000,
+        gas_price: U256::from(1),
+        input: hex::decode("60ef60005360016000f3").unwrap(),
+        ..Default::default()
+    };
+    match cita_vm::exec_dry_run_create(
+        block_data_provider.clone(),
+        state_data_provider.clone(),
+        context.clone(),
+        config.clone(),
+        tx.clone(),
+        None,
+    ) {
+        Err(cita_vm::Error::Evm(cita_vm::evm::Error::InvalidCode)) => {}
+        _ => panic!("InvalidCode expected"),
+    }
+    assert!(!cita_vm::evm::InterpreterConf::from_spec(cita_vm::evm::Spec::Constantinople).eip3541);
+    assert!(cita_vm::evm::InterpreterConf::from_spec(cita_vm::evm::Spec::London).eip3541);
+    config.evm_cfg.eip3541 = false;
+    let r = cita_vm::exec_dry_run_create(
+        block_data_provider.clone(),
+        state_data_provider.clone(),
+        context.clone(),
+        config.clone(),
+        tx.clone(),
+        None,
+    )
+    .unwrap();
+    assert_eq!(r.code, vec![0xef]);
+
+    // EOF init code copies the runtime container from its data section. The
+    // runtime calls a function which returns 2 + 2.
+    config.evm_cfg.eof = true;
+    let runtime =
+        hex::decode("ef0001010008020002000d00030300000000000002010100026002e3000160005260206000f38001e4").unwrap();
+    let tx = cita_vm::Transaction {
+        input: [
+            hex::decode("ef0001010004020001000c03002900000000036029601f60003960296000f3").unwrap(),
+            runtime.clone(),
+        ]
+        .concat(),
+        ..tx
+    };
+    let address = match cita_vm::exec(
+        block_data_provider.clone(),
+        state_data_provider.clone(),
+        context.clone(),
+        config.clone(),
+        tx.clone(),
+    )
+    .unwrap()
+    {
+        cita_vm::evm::InterpreterResult::Create(code, _, _, address) => {
+            assert_eq!(code, runtime);
+            address
+        }
+        _ => panic!("create expected"),
+    };
+    let tx = cita_vm::Transaction {
+        to: Some(address),
+        input: vec![],
+        ..tx
+    };
+    match cita_vm::exec(block_data_provider, state_data_provider, context, config, tx).unwrap() {
+        cita_vm::evm::InterpreterResult::Normal(output, _, _) => {
+            assert_eq!(U256::from_big_endian(&output), U256::from(4))
+        }
+        _ => panic!("normal result expected"),
+    }
+}
+
+#[test]
+fn test_blob_transaction() {
+    assert_eq!(cita_vm::get_blob_base_fee(0), U256::from(1));
+    assert_eq!(cita_vm::get_blob_base_fee(10_000_000), U256::from(19));
+    assert_eq!(cita_vm::get_blob_base_fee(50_000_000), U256::from(3_194_333));
+
+    let db = Arc::new(cita_vm::state::MemoryDB::new(false));
+    let mut state = cita_vm::state::State::new(db).unwrap();
+    let sender = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+    let contract = Address::from_str("0x2000000000000000000000000000000000000000").unwrap();
+    state.new_contract(&sender, U256::from(10_000_000), U256::from(1), vec![]);
+    // Returns BLOBHASH(1) as a word.
+    state.new_contract(
+        &contract,
+        U256::from(0),
+        U256::from(1),
+        hex::decode("60014960005260206000f3").unwrap(),
+    );
+    state.commit().unwrap();
+
+    let block_data_provider: Arc<dyn cita_vm::BlockDataProvider> = Arc::new(cita_vm::BlockDataProviderMock::default());
+    let state_data_provider = Arc::new(RefCell::new(state));
+    let context = cita_vm::evm::Context {
+        excess_blob_gas: 10_000_000,
+        ..Default::default()
+    };
+    let config = cita_vm::Config::default();
+
+    let mut hash = H256::from_low_u64_be(0xbb);
+    hash.0[0] = 0x01;
+    let tx = cita_vm::Transaction {
+        from: sender,
+        to: Some(contract),
+        value: U256::from(0),
+        nonce: U256::from(1),
+        gas_limit: 100_000,
+        gas_price: U256::from(1),
+        input: vec![],
+        max_fee_per_blob_gas: U256::from(19),
+        blob_versioned_hashes: vec![H256::zero(), hash],
+        ..Default::default()
+    };
+    let exec = |tx: cita_vm::Transaction| {
+        cita_vm::exec(
+            block_data_provider.clone(),
+            state_data_provider.clone(),
+            context.clone(),
+            config.clone(),
+            tx,
+        )
+    };
+
+    // Every versioned hash must start with the KZG version byte.
+    match exec(tx.clone()) {
+        Err(cita_vm::Error::InvalidBlobTransaction) => {}
+        _ => panic!("InvalidBlobTransaction expected"),
+    }
+    let tx = cita_vm::Transaction {
+        blob_versioned_hashes: vec![hash, hash],
+        ..tx
+    };
+    match exec(cita_vm::Transaction {
+        max_fee_per_blob_gas: U256::from(18),
+        ..tx.clone()
+    }) {
+        Err(cita_vm::Error::BlobGasPriceTooLow) => {}
+        _ => panic!("BlobGasPriceTooLow expected"),
+    }
+    // Two blobs at price 19 are more than the balance.
+    match exec(cita_vm::Transaction {
+        max_fee_per_blob_gas: U256::from(1_000),
+        ..tx.clone()
+    }) {
+        Err(cita_vm::Error::NotEnoughBalance) => {}
+        _ => panic!("NotEnoughBalance expected"),
+    }
+    // The blob gas prepay overflows.
+    match exec(cita_vm::Transaction {
+        max_fee_per_blob_gas: U256::max_value(),
+        ..tx.clone()
+    }) {
+        Err(cita_vm::Error::NotEnoughBalance) => {}
+        _ => panic!("NotEnoughBalance expected"),
+    }
+
+    let gas_used = match exec(tx).unwrap() {
+        cita_vm::evm::InterpreterResult::Normal(output, gas_left, _) => {
+            assert_eq!(output, hash.as_bytes().to_vec());
+            100_000 - gas_left
+        }
+        _ => panic!("normal result expected"),
+    };
+    // The blob gas is charged at the blob base fee, not the max fee.
+    assert_eq!(
+        state_data_provider.borrow_mut().balance(&sender).unwrap(),
+        U256::from(10_000_000 - gas_used - 2 * 131_072 * 19)
+    );
+}
+
+#[test]
+fn test_blob_base_fee_nested() {
+    let db = Arc::new(cita_vm::state::MemoryDB::new(false));
+    let mut state = cita_vm::state::State::new(db).unwrap();
+    let sender = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+    let caller = Address::from_str("0x2000000000000000000000000000000000000000").unwrap();
+    let callee = Address::from_str("0x3000000000000000000000000000000000000000").unwrap();
+    state.new_contract(&sender, U256::from(10_000_000), U256::from(1), vec![]);
+    // Returns the word returned by STATICCALL to the callee.
+    state.new_contract(
+        &caller,
+        U256::from(0),
+        U256::from(1),
+        hex::decode("60206000600060007330000000000000000000000000000000000000005afa5060206000f3").unwrap(),
+    );
+    // Returns BLOBBASEFEE as a word.
+    state.new_contract(
+        &callee,
+        U256::from(0),
+        U256::from(1),
+        hex::decode("4a60005260206000f3").unwrap(),
+    );
+    state.commit().unwrap();
+
+    let block_data_provider: Arc<dyn cita_vm::BlockDataProvider> = Arc::new(cita_vm::BlockDataProviderMock::default());
+    let state_data_provider = Arc::new(RefCell::new(state));
+    let context = cita_vm::evm::Context {
+        excess_blob_gas: 10_000_000,
+        ..Default::default()
+    };
+    let tx = cita_vm::Transaction {
+        from: sender,
+        to: Some(caller),
+        value: U256::from(0),
+        nonce: U256::from(1),
+        gas_limit: 100_000,
+        gas_price: U256::from(1),
+        input: vec![],
+        ..Default::default()
+    };
+    // Nested frames see the blob base fee of the transaction.
+    match cita_vm::exec(
+        block_data_provider,
+        state_data_provider,
+        context,
+        cita_vm::Config::default(),
+        tx,
+    )
+    .unwrap()
+    {
+        cita_vm::evm::InterpreterResult::Normal(output, _, _) => {
+            assert_eq!(U256::from_big_endian(&output), U256::from(19))
+        }
+        _ => panic!("normal result expected"),
+    }
+}
+
+#[test]
+fn test_selfdestruct_eip6780() {
+    let sender = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+    let contract = Address::from_str("0x2000000000000000000000000000000000000000").unwrap();
+    let beneficiary = Address::from_str("0x3000000000000000000000000000000000000000").unwrap();
+    // PUSH20 beneficiary; SELFDESTRUCT
+    let code = hex::decode("733000000000000000000000000000000000000000ff").unwrap();
+
+    let run = |eip6780: bool| {
+        let db = Arc::new(cita_vm::state::MemoryDB::new(false));
+        let mut state = cita_vm::state::State::new(db).unwrap();
+        state.new_contract(&sender, U256::from(1_000_000), U256::from(1), vec![]);
+        state.new_contract(&contract, U256::from(100), U256::from(1), code.clone());
+        state.commit().unwrap();
+
+        let block_data_provider: Arc<dyn cita_vm::BlockDataProvider> =
+            Arc::new(cita_vm::BlockDataProviderMock::default());
+        let state_data_provider = Arc::new(RefCell::new(state));
+        let mut config = cita_vm::Config::default();
+        config.evm_cfg.eip6780 = eip6780;
+        let exec = |tx: cita_vm::Transaction| {
+            cita_vm::exec(
+                block_data_provider.clone(),
+                state_data_provider.clone(),
+                cita_vm::evm::Context::default(),
+                config.clone(),
+                tx,
+            )
+            .unwrap()
+        };
+
+        let tx = cita_vm::Transaction {
+            from: sender,
+            to: Some(contract),
+            value: U256::from(0),
+            nonce: U256::from(1),
+            gas_limit: 100_000,
+            gas_price: U256::from(1),
+            input: vec![],
+            ..Default::default()
+        };
+        exec(tx.clone());
+        let created = match exec(cita_vm::Transaction {
+            to: None,
+            nonce: U256::from(2),
+            input: code.clone(),
+            ..tx
+        }) {
+            cita_vm::evm::InterpreterResult::Create(_, _, _, address) => address,
+            _ => panic!("create result expected"),
+        };
+        state_data_provider.borrow_mut().commit().unwrap();
+        let mut state = state_data_provider.borrow_mut();
+        assert_eq!(state.balance(&beneficiary).unwrap(), U256::from(100));
+        // A contract created and destroyed in the same transaction is always deleted.
+        assert!(!state.exist(&created).unwrap());
+        (state.exist(&contract).unwrap(), state.code(&contract).unwrap())
+    };
+
+    assert_eq!(run(false), (false, vec![]));
+    // Since Cancun, a pre-existing contract only sends its balance away.
+    assert_eq!(run(true), (true, code.clone()));
+    assert!(cita_vm::evm::InterpreterConf::from_spec(cita_vm::evm::Spec::Cancun).eip6780);
+    assert!(!cita_vm::evm::InterpreterConf::from_spec(cita_vm::evm::Spec::Shanghai).eip6780);
+}
+
+#[test]
+fn test_refund_spec() {
+    let sender = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+    let contract = Address::from_str("0x2000000000000000000000000000000000000000").unwrap();
+
+    let gas_paid = |evm_cfg: cita_vm::evm::InterpreterConf| {
+        let db = Arc::new(cita_vm::state::MemoryDB::new(false));
+        let mut state = cita_vm::state::State::new(db).unwrap();
+        state.new_contract(&sender, U256::from(1_000_000), U256::from(1), vec![]);
+        // Clear the storage slot 0: PUSH1 0; PUSH1 0; SSTORE
+        state.new_contract(
+            &contract,
+            U256::from(0),
+            U256::from(1),
+            hex::decode("6000600055").unwrap(),
+        );
+        state
+            .set_storage(&contract, H256::zero(), H256::from_low_u64_be(1))
+            .unwrap();
+        state.commit().unwrap();
+
+        let block_data_provider: Arc<dyn cita_vm::BlockDataProvider> =
+            Arc::new(cita_vm::BlockDataProviderMock::default());
+        let state_data_provider = Arc::new(RefCell::new(state));
+        let config = cita_vm::Config {
+            evm_cfg,
+            ..Default::default()
+        };
+        let tx = cita_vm::Transaction {
+            from: sender,
+            to: Some(contract),
+            value: U256::from(0),
+            nonce: U256::from(1),
+            gas_limit: 100_000,
+            gas_price: U256::from(1),
+            input: vec![],
+            ..Default::default()
+        };
+        cita_vm::exec(
+            block_data_provider,
+            state_data_provider.clone(),
+            cita_vm::evm::Context::default(),
+            config,
+            tx,
+        )
+        .unwrap();
+        let balance = state_data_provider.borrow_mut().balance(&sender).unwrap();
+        (U256::from(1_000_000) - balance).low_u64()
+    };
+
+    // 26006 gas is used, the refund of 15000 is capped at the half of it.
+    assert_eq!(gas_paid(cita_vm::evm::InterpreterConf::default()), 26006 - 13003);
+    // Since London, the refund is 4800 and capped at the fifth of the gas used.
+    let london = cita_vm::evm::InterpreterConf::from_spec(cita_vm::evm::Spec::London);
+    assert_eq!(london.gas_self_destruct_refund, 0);
+    assert_eq!(gas_paid(london), 26006 - 4800);
+    // A quotient of zero disables the refund.
+    let no_refund = cita_vm::evm::InterpreterConf {
+        max_refund_quotient: 0,
+        ..Default::default()
+    };
+    assert_eq!(gas_paid(no_refund), 26006);
+}
+
+#[test]
+fn test_exceptional_halt_gas() {
+    let sender = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+    let caller = Address::from_str("0x2000000000000000000000000000000000000000").unwrap();
+    let callee = Address::from_str("0x3000000000000000000000000000000000000000").unwrap();
+    // CALL the callee with 10000 gas and no value, then STOP.
+    let call = "60006000600060006000733000000000000000000000000000000000000000612710f100";
+    // STATICCALL the callee with 10000 gas, then STOP.
+    let static_call = "6000600060006000733000000000000000000000000000000000000000612710fa00";
+
+    let exec = |caller_code: &str, callee_code: &str, to: Address| {
+        let db = Arc::new(cita_vm::state::MemoryDB::new(false));
+        let mut state = cita_vm::state::State::new(db).unwrap();
+        state.new_contract(&sender, U256::from(1_000_000), U256::from(1), vec![]);
+        state.new_contract(&caller, U256::from(0), U256::from(1), hex::decode(caller_code).unwrap());
+        state.new_contract(&callee, U256::from(0), U256::from(1), hex::decode(callee_code).unwrap());
+        state.commit().unwrap();
+
+        let block_data_provider: Arc<dyn cita_vm::BlockDataProvider> =
+            Arc::new(cita_vm::BlockDataProviderMock::default());
+        let state_data_provider = Arc::new(RefCell::new(state));
+        let tx = cita_vm::Transaction {
+            from: sender,
+            to: Some(to),
+            value: U256::from(0),
+            nonce: U256::from(1),
+            gas_limit: 100_000,
+            gas_price: U256::from(1),
+            input: vec![],
+            ..Default::default()
+        };
+        let r = cita_vm::exec(
+            block_data_provider,
+            state_data_provider.clone(),
+            cita_vm::evm::Context::default(),
+            cita_vm::Config::default(),
+            tx,
+        );
+        let balance = state_data_provider.borrow_mut().balance(&sender).unwrap();
+        (r, (U256::from(1_000_000) - balance).low_u64())
+    };
+
+    let (_, gas_stop) = exec(call, "00", caller);
+    // PUSH1 0; PUSH1 0; REVERT gives back the gas left.
+    let (_, gas_revert) = exec(call, "60006000fd", caller);
+    assert_eq!(gas_revert, gas_stop + 6);
+
+    let halts = [
+        ("01", cita_vm::evm::Error::StackUnderflow), // ADD on an empty stack
+        ("600056", cita_vm::evm::Error::InvalidJumpDestination), // PUSH1 0; JUMP
+        ("5b600056", cita_vm::evm::Error::OutOfGas), // JUMPDEST; PUSH1 0; JUMP forever
+        ("fe", cita_vm::evm::Error::InvalidInstruction), // INVALID
+    ];
+    for (code, e) in halts.iter() {
+        // A nested frame consumes the 10000 gas forwarded, and the caller
+        // goes on.
+        let (r, gas) = exec(call, code, caller);
+        assert!(r.is_ok());
+        assert_eq!(gas, gas_stop + 10000, "nested {:?}", e);
+        // At the top level, the whole gas limit is consumed.
+        let (r, gas) = exec(call, code, callee);
+        match r {
+            Err(cita_vm::Error::Evm(ref err)) if err == e => {}
+            _ => panic!("{:?} expected, got {:?}", e, r),
+        }
+        assert_eq!(gas, 100_000);
+    }
+
+    // A state change in a static frame: PUSH1 1; PUSH1 0; SSTORE
+    let (_, gas_static_stop) = exec(static_call, "00", caller);
+    let (r, gas) = exec(static_call, "6001600055", caller);
+    assert!(r.is_ok());
+    assert_eq!(gas, gas_static_stop + 10000);
+}
+
+#[test]
+fn test_journaled_substate() {
+    let sender = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+    let contract = Address::from_str("0x2000000000000000000000000000000000000000").unwrap();
+    let reverted = Address::from_str("0x4000000000000000000000000000000000000000").unwrap();
+    let destructed = Address::from_str("0x5000000000000000000000000000000000000000").unwrap();
+    // Without input: TSTORE(0, 1), call itself with 2 bytes input, call itself
+    // with 1 byte input and return its output.
+    // With 2 bytes input: TSTORE(0, 2), call 0x40.., then REVERT.
+    // With 1 byte input: call 0x50.., then return TLOAD(0).
+    let code = "368060545750600160005d600060006002600060007320000000000000000000000000000000000000005af1506020600060\
+                01600060007320000000000000000000000000000000000000005af15060206000f35b600114608757600260005d60006000\
+                6000600060007340000000000000000000000000000000000000005af15060006000fd5b6000600060006000600073500000\
+                00000000000000000000000000000000005af15060005c60005260206000f3";
+
+    let db = Arc::new(cita_vm::state::MemoryDB::new(false));
+    let mut state = cita_vm::state::State::new(db).unwrap();
+    state.new_contract(&sender, U256::from(1_000_000), U256::from(1), vec![]);
+    state.new_contract(&contract, U256::from(0), U256::from(1), hex::decode(code).unwrap());
+    // CALLER; SELFDESTRUCT
+    state.new_contract(&reverted, U256::from(0), U256::from(1), hex::decode("33ff").unwrap());
+    state.new_contract(&destructed, U256::from(0), U256::from(1), hex::decode("33ff").unwrap());
+    state.commit().unwrap();
+
+    let block_data_provider: Arc<dyn cita_vm::BlockDataProvider> = Arc::new(cita_vm::BlockDataProviderMock::default());
+    let state_data_provider = Arc::new(RefCell::new(state));
+    let tx = cita_vm::Transaction {
+        from: sender,
+        to: Some(contract),
+        value: U256::from(0),
+        nonce: U256::from(1),
+        gas_limit: 1_000_000,
+        gas_price: U256::from(1),
+        input: vec![],
+        ..Default::default()
+    };
+    let r = cita_vm::exec(
+        block_data_provider,
+        state_data_provider.clone(),
+        cita_vm::evm::Context::default(),
+        cita_vm::Config::default(),
+        tx,
+    )
+    .unwrap();
+    // The transient storage is shared by frames, and the write of the
+    // reverted frame is undone.
+    match r {
+        cita_vm::evm::InterpreterResult::Normal(output, _, _) => {
+            assert_eq!(U256::from_big_endian(&output), U256::from(1))
+        }
+        _ => panic!("normal result expected"),
+    }
+    // So is the selfdestruct.
+    let mut state = state_data_provider.borrow_mut();
+    assert!(state.exist(&reverted).unwrap());
+    assert!(!state.exist(&destructed).unwrap());
+}
+
+#[test]
+fn test_reverted_logs() {
+    let sender = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+    let caller = Address::from_str("0x2000000000000000000000000000000000000000").unwrap();
+    let callee = Address::from_str("0x3000000000000000000000000000000000000000").unwrap();
+    // CALL the callee, then LOG1 with the topic 1.
+    let caller_code = "600060006000600060007330000000000000000000000000000000000000005af1506001600060\
+                       00a100";
+
+    let db = Arc::new(cita_vm::state::MemoryDB::new(false));
+    let mut state = cita_vm::state::State::new(db).unwrap();
+    state.new_contract(&sender, U256::from(1_000_000), U256::from(1), vec![]);
+    state.new_contract(&caller, U256::from(0), U256::from(1), hex::decode(caller_code).unwrap());
+    // LOG0, then REVERT.
+    state.new_contract(
+        &callee,
+        U256::from(0),
+        U256::from(1),
+        hex::decode("60006000a060006000fd").unwrap(),
+    );
+    state.commit().unwrap();
+
+    let block_data_provider: Arc<dyn cita_vm::BlockDataProvider> = Arc::new(cita_vm::BlockDataProviderMock::default());
+    let state_data_provider = Arc::new(RefCell::new(state));
+    let tx = cita_vm::Transaction {
+        from: sender,
+        to: Some(caller),
+        value: U256::from(0),
+        nonce: U256::from(1),
+        gas_limit: 100_000,
+        gas_price: U256::from(1),
+        input: vec![],
+        ..Default::default()
+    };
+    let r = cita_vm::exec(
+        block_data_provider,
+        state_data_provider,
+        cita_vm::evm::Context::default(),
+        cita_vm::Config::default(),
+        tx,
+    )
+    .unwrap();
+    // The log of the reverted frame never reaches the result.
+    match r {
+        cita_vm::evm::InterpreterResult::Normal(_, _, logs) => {
+            assert_eq!(logs.len(), 1);
+            assert_eq!(logs[0].0, caller);
+            assert_eq!(logs[0].1, vec![H256::from_low_u64_be(1)]);
+        }
+        _ => panic!("normal result expected"),
+    }
+}
+
+#[test]
+fn test_eip161_cleanup() {
+    let sender = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+    let contract = Address::from_str("0x2000000000000000000000000000000000000000").unwrap();
+    let empty = Address::from_str("0x3000000000000000000000000000000000000000").unwrap();
+    let coinbase = Address::from_str("0x4000000000000000000000000000000000000000").unwrap();
+    // CALL the empty account with no value, then REVERT.
+    let code = "6000600060006000600073300000000000000000000000000000000000000061fffff15060006000fd";
+
+    let run = |no_empty: bool, to: Address| {
+        let db = Arc::new(cita_vm::state::MemoryDB::new(false));
+        let mut state = cita_vm::state::State::new(db).unwrap();
+        state.new_contract(&sender, U256::from(1_000_000), U256::from(1), vec![]);
+        state.new_contract(&contract, U256::from(0), U256::from(1), hex::decode(code).unwrap());
+        state.new_contract(&empty, U256::from(0), U256::from(0), vec![]);
+        state.new_contract(&coinbase, U256::from(0), U256::from(0), vec![]);
+        state.commit().unwrap();
+
+        let block_data_provider: Arc<dyn cita_vm::BlockDataProvider> =
+            Arc::new(cita_vm::BlockDataProviderMock::default());
+        let state_data_provider = Arc::new(RefCell::new(state));
+        let context = cita_vm::evm::Context {
+            coinbase,
+            ..Default::default()
+        };
+        let mut config = cita_vm::Config::default();
+        config.evm_cfg.no_empty = no_empty;
+        let tx = cita_vm::Transaction {
+            from: sender,
+            to: Some(to),
+            value: U256::from(0),
+            nonce: U256::from(1),
+            gas_limit: 100_000,
+            gas_price: U256::from(0),
+            input: vec![],
+            ..Default::default()
+        };
+        cita_vm::exec(block_data_provider, state_data_provider.clone(), context, config, tx).unwrap();
+        state_data_provider.borrow_mut().commit().unwrap();
+        let mut state = state_data_provider.borrow_mut();
+        (state.exist(&empty).unwrap(), state.exist(&coinbase).unwrap())
+    };
+
+    // A zero value transfer touches the empty account, so it is deleted, and
+    // so is the empty coinbase which receives no fee.
+    assert_eq!(run(true, empty), (false, false));
+    assert_eq!(run(false, empty), (true, true));
+    // The touch of a reverted frame is undone.
+    assert_eq!(run(true, contract), (true, false));
+}
+
+#[test]
+fn test_eip158_specific() {
+    #[derive(Debug)]
+    struct SplitPolicy {
+        treasury: Address,
+    }
+
+    impl cita_vm::FeePolicy for SplitPolicy {
+        fn distribute(
+            &self,
+            evm_context: &cita_vm::evm::Context,
+            request: &cita_vm::evm::InterpreterParams,
+            gas_used: u64,
+        ) -> Vec<(Address, U256)> {
+            let fee = request.gas_price * gas_used;
+            vec![(evm_context.coinbase, fee / 2), (self.treasury, fee / 2)]
+        }
+    }
+
+    let sender = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+    let creator = Address::from_str("0x2000000000000000000000000000000000000000").unwrap();
+    let empty = Address::from_str("0x3000000000000000000000000000000000000000").unwrap();
+    let coinbase = Address::from_str("0x4000000000000000000000000000000000000000").unwrap();
+    let treasury = Address::from_str("0x5000000000000000000000000000000000000000").unwrap();
+    // CREATE with empty initcode.
+    let code = "600060006000f000";
+
+    let run = |no_empty: bool| {
+        let db = Arc::new(cita_vm::state::MemoryDB::new(false));
+        let mut state = cita_vm::state::State::new(db).unwrap();
+        state.new_contract(&sender, U256::from(1_000_000), U256::from(1), vec![]);
+        state.new_contract(&creator, U256::from(0), U256::from(1), hex::decode(code).unwrap());
+        for address in [empty, coinbase, treasury].iter() {
+            state.new_contract(address, U256::from(0), U256::from(0), vec![]);
+        }
+        state.commit().unwrap();
+
+        let block_data_provider: Arc<dyn cita_vm::BlockDataProvider> =
+            Arc::new(cita_vm::BlockDataProviderMock::default());
+        let state_data_provider = Arc::new(RefCell::new(state));
+        let context = cita_vm::evm::Context {
+            coinbase,
+            ..Default::default()
+        };
+        let mut config = cita_vm::Config {
+            fee_policy: Arc::new(SplitPolicy { treasury }),
+            ..Default::default()
+        };
+        config.evm_cfg.no_empty = no_empty;
+        let tx = cita_vm::Transaction {
+            from: sender,
+            to: Some(creator),
+            value: U256::from(0),
+            nonce: U256::from(1),
+            gas_limit: 100_000,
+            gas_price: U256::from(0),
+            input: vec![],
+            ..Default::default()
+        };
+        let exec = |tx: cita_vm::Transaction| {
+            cita_vm::exec(
+                block_data_provider.clone(),
+                state_data_provider.clone(),
+                context.clone(),
+                config.clone(),
+                tx,
+            )
+            .unwrap();
+            state_data_provider.borrow_mut().commit().unwrap();
+        };
+        exec(tx.clone());
+        // A zero value transfer to the empty account.
+        exec(cita_vm::Transaction {
+            to: Some(empty),
+            nonce: U256::from(2),
+            ..tx
+        });
+
+        let mut state = state_data_provider.borrow_mut();
+        let created = cita_vm::create_address_from_address_and_nonce(&creator, &U256::from(1));
+        (
+            state.nonce(&created).unwrap(),
+            state.exist(&created).unwrap(),
+            state.exist(&empty).unwrap(),
+            state.exist(&coinbase).unwrap(),
+            state.exist(&treasury).unwrap(),
+        )
+    };
+
+    // Contracts are created with nonce 1, so they are not empty. The touched
+    // receiver and the fee receivers which get no fee are deleted.
+    assert_eq!(run(true), (U256::from(1), true, false, false, false));
+    // Without EIP 161 nothing is deleted, and contracts start from nonce 0.
+    assert_eq!(run(false), (U256::from(0), true, true, true, true));
+}
+
+#[test]
+fn test_nonce_mode() {
+    let sender = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+    let contract = Address::from_str("0x2000000000000000000000000000000000000000").unwrap();
+
+    let db = Arc::new(cita_vm::state::MemoryDB::new(false));
+    let mut state = cita_vm::state::State::new(db).unwrap();
+    state.new_contract(&sender, U256::from(1_000_000), U256::from(1), vec![]);
+    // PUSH1 0; PUSH1 0; REVERT
+    state.new_contract(
+        &contract,
+        U256::from(0),
+        U256::from(1),
+        hex::decode("60006000fd").unwrap(),
+    );
+    state.commit().unwrap();
+
+    let block_data_provider: Arc<dyn cita_vm::BlockDataProvider> = Arc::new(cita_vm::BlockDataProviderMock::default());
+    let state_data_provider = Arc::new(RefCell::new(state));
+    let exec = |nonce_mode: cita_vm::NonceMode, tx: cita_vm::Transaction| {
+        let config = cita_vm::Config {
+            nonce_mode,
+            ..Default::default()
+        };
+        cita_vm::exec(
+            block_data_provider.clone(),
+            state_data_provider.clone(),
+            cita_vm::evm::Context::default(),
+            config,
+            tx,
+        )
+    };
+    let nonce = || state_data_provider.borrow_mut().nonce(&sender).unwrap();
+    let tx = cita_vm::Transaction {
+        from: sender,
+        to: Some(contract),
+        value: U256::from(0),
+        nonce: U256::from(0),
+        gas_limit: 100_000,
+        gas_price: U256::from(1),
+        input: vec![],
+        ..Default::default()
+    };
+
+    // CITA's behavior: the nonce is neither checked nor increased.
+    exec(cita_vm::NonceMode::Cita, tx.clone()).unwrap();
+    assert_eq!(nonce(), U256::from(1));
+    exec(cita_vm::NonceMode::CitaChecked, tx.clone()).unwrap();
+    match exec(
+        cita_vm::NonceMode::CitaChecked,
+        cita_vm::Transaction {
+            nonce: U256::from(1),
+            ..tx.clone()
+        },
+    ) {
+        Err(cita_vm::Error::InvalidNonce { expected, actual }) => {
+            assert_eq!((expected, actual), (U256::from(0), U256::from(1)))
+        }
+        _ => panic!("InvalidNonce expected"),
+    }
+
+    // The strict mode increases the nonce even if the transaction reverts.
+    match exec(cita_vm::NonceMode::Strict, tx.clone()) {
+        Err(cita_vm::Error::InvalidNonce { expected, actual }) => {
+            assert_eq!((expected, actual), (U256::from(1), U256::from(0)))
+        }
+        _ => panic!("InvalidNonce expected"),
+    }
+    assert_eq!(nonce(), U256::from(1));
+    match exec(
+        cita_vm::NonceMode::Strict,
+        cita_vm::Transaction {
+            nonce: U256::from(1),
+            ..tx.clone()
+        },
+    ) {
+        Ok(cita_vm::evm::InterpreterResult::Revert(..)) => {}
+        _ => panic!("revert result expected"),
+    }
+    assert_eq!(nonce(), U256::from(2));
+    // The contract address is derived from the nonce of tx.
+    let tx = cita_vm::Transaction {
+        to: None,
+        nonce: U256::from(2),
+        ..tx
+    };
+    match exec(cita_vm::NonceMode::Strict, tx).unwrap() {
+        cita_vm::evm::InterpreterResult::Create(_, _, _, address) => assert_eq!(
+            address,
+            cita_vm::create_address_from_address_and_nonce(&sender, &U256::from(2))
+        ),
+        _ => panic!("create result expected"),
+    }
+    assert_eq!(nonce(), U256::from(3));
+}
+
+#[test]
+fn test_fee_policy() {
+    // A sponsor pays for the gas, half of the fee is burned and the rest is
+    // split between the coinbase and a treasury.
+    #[derive(Debug)]
+    struct SponsoredPolicy {
+        sponsor: Address,
+        treasury: Address,
+    }
+
+    impl cita_vm::FeePolicy for SponsoredPolicy {
+        fn payer(&self, _: &cita_vm::evm::InterpreterParams) -> Address {
+            self.sponsor
+        }
+
+        fn distribute(
+            &self,
+            evm_context: &cita_vm::evm::Context,
+            request: &cita_vm::evm::InterpreterParams,
+            gas_used: u64,
+        ) -> Vec<(Address, U256)> {
+            let fee = request.gas_price * gas_used;
+            vec![(evm_context.coinbase, fee / 4), (self.treasury, fee / 4)]
+        }
+    }
+
+    let sender = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+    let receiver = Address::from_str("0x2000000000000000000000000000000000000000").unwrap();
+    let sponsor = Address::from_str("0x3000000000000000000000000000000000000000").unwrap();
+    let treasury = Address::from_str("0x4000000000000000000000000000000000000000").unwrap();
+    let coinbase = Address::from_str("0x5000000000000000000000000000000000000000").unwrap();
+
+    let db = Arc::new(cita_vm::state::MemoryDB::new(false));
+    let mut state = cita_vm::state::State::new(db).unwrap();
+    state.new_contract(&sender, U256::from(1_000), U256::from(1), vec![]);
+    state.new_contract(&sponsor, U256::from(1_000_000), U256::from(1), vec![]);
+    state.commit().unwrap();
+
+    let block_data_provider: Arc<dyn cita_vm::BlockDataProvider> = Arc::new(cita_vm::BlockDataProviderMock::default());
+    let state_data_provider = Arc::new(RefCell::new(state));
+    let context = cita_vm::evm::Context {
+        coinbase,
+        ..Default::default()
+    };
+    let config = cita_vm::Config {
+        fee_policy: Arc::new(SponsoredPolicy { sponsor, treasury }),
+        ..Default::default()
+    };
+    let tx = cita_vm::Transaction {
+        from: sender,
+        to: Some(receiver),
+        value: U256::from(100),
+        nonce: U256::from(0),
+        gas_limit: 100_000,
+        gas_price: U256::from(2),
+        input: vec![],
+        ..Default::default()
+    };
+    cita_vm::exec(
+        block_data_provider.clone(),
+        state_data_provider.clone(),
+        context.clone(),
+        config.clone(),
+        tx.clone(),
+    )
+    .unwrap();
+
+    let balance = |address: &Address| state_data_provider.borrow_mut().balance(address).unwrap();
+    assert_eq!(balance(&sender), U256::from(900));
+    assert_eq!(balance(&receiver), U256::from(100));
+    assert_eq!(balance(&sponsor), U256::from(1_000_000 - 21_000 * 2));
+    assert_eq!(balance(&coinbase), U256::from(21_000 / 2));
+    assert_eq!(balance(&treasury), U256::from(21_000 / 2));
+
+    // The sender has to afford the value even if it does not pay for the gas.
+    let tx = cita_vm::Transaction {
+        value: U256::from(1_000),
+        ..tx
+    };
+    match cita_vm::exec(block_data_provider, state_data_provider.clone(), context, config, tx) {
+        Err(cita_vm::Error::NotEnoughBalance) => {}
+        _ => panic!("NotEnoughBalance expected"),
+    }
+}
+
+#[test]
+fn test_account_quota_limit() {
+    let sender = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+    let vip = Address::from_str("0x2000000000000000000000000000000000000000").unwrap();
+    let receiver = Address::from_str("0x3000000000000000000000000000000000000000").unwrap();
+
+    let db = Arc::new(cita_vm::state::MemoryDB::new(false));
+    let mut state = cita_vm::state::State::new(db).unwrap();
+    state.new_contract(&sender, U256::from(1_000_000), U256::from(1), vec![]);
+    state.new_contract(&vip, U256::from(1_000_000), U256::from(1), vec![]);
+    state.commit().unwrap();
+
+    let mut config = cita_vm::Config {
+        account_quota_limit: 50_000,
+        ..Default::default()
+    };
+    config.specific_quota_limits.insert(vip, 100_000);
+    let executive = cita_vm::Executive::new(Arc::new(cita_vm::BlockDataProviderMock::default()), state, config);
+    let tx = |from: Address| cita_vm::Transaction {
+        from,
+        to: Some(receiver),
+        value: U256::from(1),
+        nonce: U256::from(0),
+        gas_limit: 25_000,
+        gas_price: U256::from(1),
+        input: vec![],
+        ..Default::default()
+    };
+
+    // Only the gas used is accounted, so the second transaction is still
+    // allowed, but the third one exceeds the limit.
+    executive.exec(cita_vm::evm::Context::default(), tx(sender)).unwrap();
+    assert_eq!(executive.quota_used(&sender), 21_000);
+    executive.exec(cita_vm::evm::Context::default(), tx(sender)).unwrap();
+    assert_eq!(executive.quota_used(&sender), 42_000);
+    match executive.exec(cita_vm::evm::Context::default(), tx(sender)) {
+        Err(cita_vm::Error::ExccedAccountQuotaLimit) => {}
+        _ => panic!("ExccedAccountQuotaLimit expected"),
+    }
+    assert_eq!(executive.quota_used(&sender), 42_000);
+    assert_eq!(
+        executive.state_provider.borrow_mut().balance(&receiver).unwrap(),
+        U256::from(2)
+    );
+
+    // The specific limit overrides the default one.
+    for _ in 0..4 {
+        executive.exec(cita_vm::evm::Context::default(), tx(vip)).unwrap();
+    }
+    assert_eq!(executive.quota_used(&vip), 84_000);
+
+    // The quota is restored in a new block.
+    executive.reset_quota_used();
+    executive.exec(cita_vm::evm::Context::default(), tx(sender)).unwrap();
+    assert_eq!(executive.quota_used(&sender), 21_000);
+}
+
+#[test]
+fn test_authorizer() {
+    #[derive(Debug)]
+    struct Permissions {
+        deployer: Address,
+        protected: Address,
+    }
+
+    impl cita_vm::Authorizer for Permissions {
+        fn can_create(&self, sender: &Address) -> bool {
+            *sender == self.deployer
+        }
+
+        fn can_call(&self, _: &Address, contract: &Address, _: &[u8]) -> bool {
+            *contract != self.protected
+        }
+    }
+
+    let sender = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+    let deployer = Address::from_str("0x2000000000000000000000000000000000000000").unwrap();
+    let protected = Address::from_str("0x3000000000000000000000000000000000000000").unwrap();
+    let proxy = Address::from_str("0x4000000000000000000000000000000000000000").unwrap();
+    let coinbase = Address::from_str("0x5000000000000000000000000000000000000000").unwrap();
+
+    let db = Arc::new(cita_vm::state::MemoryDB::new(false));
+    let mut state = cita_vm::state::State::new(db).unwrap();
+    state.new_contract(&sender, U256::from(1_000_000), U256::from(1), vec![]);
+    state.new_contract(&deployer, U256::from(1_000_000), U256::from(1), vec![]);
+    state.new_contract(&protected, U256::from(0), U256::from(1), hex::decode("00").unwrap());
+    // Call the protected contract, and store the result to slot 0.
+    state.new_contract(
+        &proxy,
+        U256::from(0),
+        U256::from(1),
+        hex::decode("6000600060006000600073300000000000000000000000000000000000000061fffff160005500").unwrap(),
+    );
+    state.commit().unwrap();
+
+    let block_data_provider: Arc<dyn cita_vm::BlockDataProvider> = Arc::new(cita_vm::BlockDataProviderMock::default());
+    let state_data_provider = Arc::new(RefCell::new(state));
+    let exec = |config: cita_vm::Config, tx: cita_vm::Transaction| {
+        let context = cita_vm::evm::Context {
+            coinbase,
+            ..Default::default()
+        };
+        cita_vm::exec(
+            block_data_provider.clone(),
+            state_data_provider.clone(),
+            context,
+            config,
+            tx,
+        )
+    };
+    let balance = |address: &Address| state_data_provider.borrow_mut().balance(address).unwrap();
+    let config = cita_vm::Config {
+        authorizer: Arc::new(Permissions { deployer, protected }),
+        ..Default::default()
+    };
+    let tx = cita_vm::Transaction {
+        from: sender,
+        to: None,
+        value: U256::from(0),
+        nonce: U256::from(0),
+        gas_limit: 100_000,
+        gas_price: U256::from(1),
+        input: hex::decode("00").unwrap(),
+        ..Default::default()
+    };
+
+    // A denied transaction is charged no gas by default.
+    match exec(config.clone(), tx.clone()) {
+        Err(cita_vm::Error::NoContractPermission) => {}
+        _ => panic!("NoContractPermission expected"),
+    }
+    assert_eq!(balance(&sender), U256::from(1_000_000));
+    exec(
+        config.clone(),
+        cita_vm::Transaction {
+            from: deployer,
+            ..tx.clone()
+        },
+    )
+    .unwrap();
+
+    // Or charged gas_permission_denied.
+    let coinbase_balance = balance(&coinbase);
+    let tx = cita_vm::Transaction {
+        to: Some(protected),
+        input: vec![],
+        ..tx
+    };
+    match exec(
+        cita_vm::Config {
+            gas_permission_denied: 1_000,
+            ..config.clone()
+        },
+        tx.clone(),
+    ) {
+        Err(cita_vm::Error::NoCallPermission(address)) => assert_eq!(address, protected),
+        _ => panic!("NoCallPermission expected"),
+    }
+    assert_eq!(balance(&sender), U256::from(1_000_000 - 1_000));
+    assert_eq!(balance(&coinbase), coinbase_balance + U256::from(1_000));
+    // A sender who cannot prepay the gas is denied without being charged.
+    match exec(
+        cita_vm::Config {
+            gas_permission_denied: 1_000,
+            ..config.clone()
+        },
+        cita_vm::Transaction {
+            gas_price: U256::from(100),
+            ..tx.clone()
+        },
+    ) {
+        Err(cita_vm::Error::NoCallPermission(address)) => assert_eq!(address, protected),
+        _ => panic!("NoCallPermission expected"),
+    }
+    assert_eq!(balance(&sender), U256::from(1_000_000 - 1_000));
+    assert_eq!(balance(&coinbase), coinbase_balance + U256::from(1_000));
+
+    // Nested calls are checked only if check_nested_permission is set.
+    let tx = cita_vm::Transaction { to: Some(proxy), ..tx };
+    let slot = || {
+        state_data_provider
+            .borrow_mut()
+            .get_storage(&proxy, &H256::zero())
+            .unwrap()
+    };
+    exec(
+        cita_vm::Config {
+            check_nested_permission: true,
+            ..config.clone()
+        },
+        tx.clone(),
+    )
+    .unwrap();
+    assert_eq!(slot(), H256::zero());
+    // The denied frame is charged gas_permission_denied, instead of all the
+    // gas forwarded to it.
+    let gas_paid = |gas_permission_denied: u64| {
+        let before = balance(&sender);
+        exec(
+            cita_vm::Config {
+                check_nested_permission: true,
+                gas_permission_denied,
+                ..config.clone()
+            },
+            tx.clone(),
+        )
+        .unwrap();
+        (before - balance(&sender)).low_u64()
+    };
+    assert_eq!(gas_paid(1_000), gas_paid(0) + 1_000);
+    exec(config.clone(), tx.clone()).unwrap();
+    assert_eq!(slot(), H256::from_low_u64_be(1));
+
+    // In the strict nonce mode, a denied transaction consumes its nonce only
+    // if it is charged.
+    let nonce = || state_data_provider.borrow_mut().nonce(&sender).unwrap();
+    let config = cita_vm::Config {
+        nonce_mode: cita_vm::NonceMode::Strict,
+        ..config
+    };
+    let tx = cita_vm::Transaction {
+        to: Some(protected),
+        nonce: U256::from(1),
+        ..tx
+    };
+    assert!(exec(config.clone(), tx.clone()).is_err());
+    assert_eq!(nonce(), U256::from(1));
+    assert!(exec(
+        cita_vm::Config {
+            gas_permission_denied: 1_000,
+            ..config
+        },
+        tx
+    )
+    .is_err());
+    assert_eq!(nonce(), U256::from(2));
+}
+
+#[test]
+fn test_authorizer_blob_transaction() {
+    #[derive(Debug)]
+    struct DenyAll;
+
+    impl cita_vm::Authorizer for DenyAll {
+        fn can_send(&self, _: &Address) -> bool {
+            false
+        }
+    }
+
+    let sender = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+    let contract = Address::from_str("0x2000000000000000000000000000000000000000").unwrap();
+
+    let db = Arc::new(cita_vm::state::MemoryDB::new(false));
+    let mut state = cita_vm::state::State::new(db).unwrap();
+    state.new_contract(&sender, U256::from(10_000_000), U256::from(1), vec![]);
+    state.new_contract(&contract, U256::from(0), U256::from(1), hex::decode("00").unwrap());
+    state.commit().unwrap();
+
+    let block_data_provider: Arc<dyn cita_vm::BlockDataProvider> = Arc::new(cita_vm::BlockDataProviderMock::default());
+    let state_data_provider = Arc::new(RefCell::new(state));
+    let context = cita_vm::evm::Context {
+        excess_blob_gas: 10_000_000,
+        ..Default::default()
+    };
+    let mut hash = H256::from_low_u64_be(0xbb);
+    hash.0[0] = 0x01;
+    let tx = cita_vm::Transaction {
+        from: sender,
+        to: Some(contract),
+        value: U256::from(0),
+        nonce: U256::from(1),
+        gas_limit: 100_000,
+        gas_price: U256::from(1),
+        input: vec![],
+        max_fee_per_blob_gas: U256::from(19),
+        blob_versioned_hashes: vec![hash],
+        ..Default::default()
+    };
+    let exec = |gas_permission_denied: u64| {
+        let config = cita_vm::Config {
+            authorizer: Arc::new(DenyAll),
+            gas_permission_denied,
+            ..Default::default()
+        };
+        match cita_vm::exec(
+            block_data_provider.clone(),
+            state_data_provider.clone(),
+            context.clone(),
+            config,
+            tx.clone(),
+        ) {
+            Err(cita_vm::Error::NoTransactionPermission) => {}
+            _ => panic!("NoTransactionPermission expected"),
+        }
+    };
+    let balance = || state_data_provider.borrow_mut().balance(&sender).unwrap();
+
+    // The blob gas is never burned for a denied transaction.
+    exec(0);
+    assert_eq!(balance(), U256::from(10_000_000));
+    exec(1_000);
+    assert_eq!(balance(), U256::from(10_000_000 - 1_000));
+}
+
+#[test]
+fn test_amend() {
+    let admin = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+    let contract = Address::from_str("0x2000000000000000000000000000000000000000").unwrap();
+
+    let db = Arc::new(cita_vm::state::MemoryDB::new(false));
+    let mut state = cita_vm::state::State::new(db).unwrap();
+    state.new_contract(&contract, U256::from(100), U256::from(1), hex::decode("00").unwrap());
+    state.commit().unwrap();
+
+    let config = cita_vm::Config {
+        admin: Some(admin),
+        ..Default::default()
+    };
+    let executive = cita_vm::Executive::new(Arc::new(cita_vm::BlockDataProviderMock::default()), state, config);
+    let key = H256::from_low_u64_be(1);
+    let amends = vec![
+        cita_vm::Amend::Code(contract, hex::decode("6000").unwrap()),
+        cita_vm::Amend::Abi(contract, b"[]".to_vec()),
+        cita_vm::Amend::Storage(contract, key, H256::from_low_u64_be(2)),
+        cita_vm::Amend::Balance(contract, U256::from(30)),
+    ];
+
+    match executive.exec_amend(contract, amends.clone()) {
+        Err(cita_vm::Error::NoAmendPermission) => {}
+        _ => panic!("NoAmendPermission expected"),
+    }
+
+    let receipt = executive.exec_amend(admin, amends.clone()).unwrap();
+    assert_eq!(receipt.amends, amends);
+    assert_eq!(
+        receipt.previous,
+        vec![
+            cita_vm::Amend::Code(contract, hex::decode("00").unwrap()),
+            cita_vm::Amend::Abi(contract, vec![]),
+            cita_vm::Amend::Storage(contract, key, H256::zero()),
+            cita_vm::Amend::Balance(contract, U256::from(100)),
+        ]
+    );
+    {
+        let mut state = executive.state_provider.borrow_mut();
+        assert_eq!(state.code(&contract).unwrap(), hex::decode("6000").unwrap());
+        assert_eq!(state.abi(&contract).unwrap(), b"[]".to_vec());
+        assert_eq!(state.get_storage(&contract, &key).unwrap(), H256::from_low_u64_be(2));
+        assert_eq!(state.balance(&contract).unwrap(), U256::from(30));
+    }
+
+    // Amends are applied atomically.
+    let amends = vec![
+        cita_vm::Amend::Balance(contract, U256::from(50)),
+        cita_vm::Amend::Code(contract, vec![0; 0x10000]),
+    ];
+    match executive.exec_amend(admin, amends) {
+        Err(cita_vm::Error::ExccedMaxCodeSize) => {}
+        _ => panic!("ExccedMaxCodeSize expected"),
+    }
+    let mut state = executive.state_provider.borrow_mut();
+    assert_eq!(state.balance(&contract).unwrap(), U256::from(30));
+    assert_eq!(state.code(&contract).unwrap(), hex::decode("6000").unwrap());
+}
+
+#[test]
+fn test_state_diff() {
+    let sender = Address::from_str("0x1000000000000000000000000000000000000000").unwrap();
+    let contract = Address::from_str("0x2000000000000000000000000000000000000000").unwrap();
+    let coinbase = Address::from_str("0x5000000000000000000000000000000000000000").unwrap();
+    let key = H256::from_low_u64_be(1);
+
+    let db = Arc::new(cita_vm::state::MemoryDB::new(false));
+    let mut state = cita_vm::state::State::new(db).unwrap();
+    state.new_contract(&sender, U256::from(1_000_000), U256::from(1), vec![]);
+    // PUSH1 2; PUSH1 1; SSTORE; STOP
+    state.new_contract(
+        &contract,
+        U256::from(0),
+        U256::from(1),
+        hex::decode("600260015500").unwrap(),
+    );
+    state.set_storage(&contract, key, H256::from_low_u64_be(5)).unwrap();
+    state.commit().unwrap();
+
+    let executive = cita_vm::Executive::new(
+        Arc::new(cita_vm::BlockDataProviderMock::default()),
+        state,
+        cita_vm::Config::default(),
+    );
+    let tx = cita_vm::Transaction {
+        from: sender,
+        to: Some(contract),
+        value: U256::from(0),
+        nonce: U256::from(0),
+        gas_limit: 100_000,
+        gas_price: U256::from(1),
+        input: vec![],
+        ..Default::default()
+    };
+    let context = cita_vm::evm::Context {
+        coinbase,
+        ..Default::default()
+    };
+    let r = executive.exec_with_state_diff(context, tx.clone()).unwrap();
+    let gas_used = match r.result.unwrap() {
+        cita_vm::evm::InterpreterResult::Normal(_, gas_left, _) => 100_000 - gas_left,
+        _ => panic!("normal result expected"),
+    };
+    let diff = r.state_diff;
+
+    assert_eq!(diff.pre.len(), 2);
+    assert_eq!(diff.pre[&sender].balance, Some(U256::from(1_000_000)));
+    assert_eq!(diff.post[&sender].balance, Some(U256::from(1_000_000 - gas_used)));
+    assert_eq!(diff.post[&sender].nonce, None);
+    assert_eq!(diff.pre[&contract].storage[&key], H256::from_low_u64_be(5));
+    assert_eq!(diff.post[&contract].storage[&key], H256::from_low_u64_be(2));
+    assert_eq!(diff.post[&contract].balance, None);
+    assert_eq!(diff.post[&coinbase].balance, Some(U256::from(gas_used)));
+    assert_eq!(diff.created().into_iter().collect::<Vec<_>>(), vec![coinbase]);
+    assert!(diff.deleted().is_empty());
+
+    let json = serde_json::to_value(&diff).unwrap();
+    assert_eq!(
+        json["post"]["0x2000000000000000000000000000000000000000"]["storage"]
+            ["0x0000000000000000000000000000000000000000000000000000000000000001"],
+        "0x0000000000000000000000000000000000000000000000000000000000000002"
+    );
+    assert!(json["post"]["0x2000000000000000000000000000000000000000"]["balance"].is_null());
+
+    // A second transaction is compared with the state after the first one.
+    let diff = executive
+        .exec_with_state_diff(cita_vm::evm::Context::default(), tx)
+        .unwrap()
+        .state_diff;
+    assert!(!diff.pre.contains_key(&contract));
+    // The checkpoint taken for the diff is discarded, so the state commits.
+    executive.state_provider.borrow_mut().commit().unwrap();
+}
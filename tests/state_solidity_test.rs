@@ -44,6 +44,7 @@ fn test_solidity_simplestorage() {
         gas_limit: 80000,
         gas_price: U256::from(1),
         input: hex::decode("60fe47b1000000000000000000000000000000000000000000000000000000000000002a").unwrap(),
+        ..Default::default()
     };
     let _ = cita_vm::exec(
         block_data_provider.clone(),
@@ -63,6 +64,7 @@ fn test_solidity_simplestorage() {
         gas_limit: 80000,
         gas_price: U256::from(1),
         input: hex::decode("6d4ce63c").unwrap(),
+        ..Default::default()
     };
     let r = cita_vm::exec(
         block_data_provider.clone(),
@@ -91,6 +93,7 @@ fn test_solidity_simplestorage() {
         gas_limit: 80000,           // Give me a large enougth value plz.
         gas_price: U256::from(1),   // Omited due to solidity's check.
         input: hex::decode("6d4ce63c").unwrap(),
+        ..Default::default()
     };
     let r = cita_vm::exec_static(block_data_provider.clone(), state_data_provider, context, config, tx).unwrap();
     debug!("{:?}", r);
@@ -262,6 +265,7 @@ fn test_solidity_erc20() {
         gas_limit: 8_000_000,
         gas_price: U256::from(1),
         input: hex::decode(code).unwrap(),
+        ..Default::default()
     };
     let r = cita_vm::exec(
         block_data_provider.clone(),
@@ -286,6 +290,7 @@ fn test_solidity_erc20() {
         gas_limit: 80000,
         gas_price: U256::from(1),
         input: hex::decode("70a082310000000000000000000000001000000000000000000000000000000000000000").unwrap(),
+        ..Default::default()
     };
     let r = cita_vm::exec(
         block_data_provider.clone(),
@@ -315,6 +320,7 @@ fn test_solidity_erc20() {
 "a9059cbb0000000000000000000000001000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000a",
         )
         .unwrap(),
+        ..Default::default()
     };
     let r = cita_vm::exec(
         block_data_provider.clone(),
@@ -357,6 +363,7 @@ fn test_solidity_erc20() {
         gas_limit: 80000,
         gas_price: U256::from(1),
         input: hex::decode("70a082310000000000000000000000001000000000000000000000000000000000000000").unwrap(),
+        ..Default::default()
     };
     let r = cita_vm::exec(block_data_provider.clone(), state_data_provider, context, config, tx).unwrap();
     match r {
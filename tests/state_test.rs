@@ -8,15 +8,21 @@ use std::sync::Arc;
 use std::thread;
 
 fn test_json_file(p: &str) {
+    test_json_file_with(p, false)
+}
+
+// Check the post states of EIP158 if eip158 is set, otherwise Homestead.
+fn test_json_file_with(p: &str, eip158: bool) {
     let f = fs::File::open(p).unwrap();
     let t = cita_vm::json_tests::general_state_test::Test::load(f).unwrap();
     for (name, data) in t.into_iter() {
-        let data_post_homestead = data.post.unwrap().homestead;
-        if data_post_homestead.is_none() {
+        let post = data.post.unwrap();
+        let data_post = if eip158 { post.eip158 } else { post.homestead };
+        if data_post.is_none() {
             continue;
         }
 
-        for (i, postdata) in data_post_homestead.unwrap().iter().enumerate() {
+        for (i, postdata) in data_post.unwrap().iter().enumerate() {
             io::stderr()
                 .write_all(format!("{}::{}::{}\n", p, name, i).as_bytes())
                 .unwrap();
@@ -55,9 +61,14 @@ fn test_json_file(p: &str) {
                 number: string_2_u256(data.env.current_number.clone()),
                 timestamp: string_2_u256(data.env.current_timestamp.clone()).low_u64(),
                 difficulty: string_2_u256(data.env.current_difficulty.clone()),
+                ..Default::default()
             };
             let mut cfg = Config::default();
             cfg.block_gas_limit = string_2_u256(data.env.current_gas_limit.clone()).low_u64();
+            if eip158 {
+                cfg.gas_schedule = GasSchedule::constantinople();
+                cfg.evm_cfg = get_interpreter_conf_eip158();
+            }
             let mut tx = Transaction {
                 from: secret_2_address(data.transaction.secret_key.as_str()),
                 to: None,
@@ -66,6 +77,7 @@ fn test_json_file(p: &str) {
                 gas_limit: string_2_u256(str_gas).low_u64(),
                 gas_price: string_2_u256(data.transaction.gas_price.clone()),
                 input: string_2_bytes(str_data),
+                ..Default::default()
             };
             if !data.transaction.to.is_empty() {
                 tx.to = Some(string_2_address(data.transaction.to.clone()));
@@ -86,6 +98,23 @@ fn test_json_file(p: &str) {
     }
 }
 
+// The gas costs of EIP150 and the rules of EIP158.
+fn get_interpreter_conf_eip158() -> evm::InterpreterConf {
+    evm::InterpreterConf {
+        no_empty: true,
+        eip3541: false,
+        max_initcode_size: u64::MAX,
+        gas_exp_byte: 50,
+        gas_balance: 400,
+        gas_sload: 200,
+        gas_call: 700,
+        gas_self_destruct: 5000,
+        gas_extcode: 700,
+        gas_self_destruct_new_account: 25000,
+        ..Default::default()
+    }
+}
+
 #[allow(dead_code)]
 fn test_json_path_skip(p: &str, skip: Vec<String>) {
     let info = fs::metadata(p).unwrap();
@@ -2480,3 +2509,17 @@ fn test_state_pass() {
         test_json_path(r"../jsondata/GeneralStateTests/stRefundTest");
     }).unwrap().join().unwrap();
 }
+
+#[test]
+fn test_state_eip158() {
+    thread::Builder::new()
+        .stack_size(134_217_728)
+        .spawn(move || {
+            for entry in fs::read_dir("../jsondata/GeneralStateTests/stEIP158Specific").unwrap() {
+                test_json_file_with(entry.unwrap().path().to_str().unwrap(), true);
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}